@@ -0,0 +1,85 @@
+use crate::credentials::BotCredentials;
+
+#[cfg(any(test, feature = "testing"))]
+mod mock;
+#[cfg(any(test, feature = "testing"))]
+pub use mock::{MockChat, MockMessage, MockSink, MockSource};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use twitch_irc::message::{IRCMessage, PrivmsgMessage, ServerMessage, TwitchUserBasics};
+use twitch_irc::{SecureTCPTransport, TwitchIRCClient};
+
+/// Where the bot's messages and joins go.
+#[async_trait]
+pub trait ChatSink: Send + Sync {
+    async fn say(&self, channel: &str, text: String) -> Result<(), String>;
+    /// Sends `text` as a reply to the message with id `reply_to`.
+    async fn reply(&self, channel: &str, reply_to: &str, text: String) -> Result<(), String>;
+    fn join(&self, channel: &str) -> Result<(), String>;
+    fn part(&self, channel: &str);
+}
+
+/// Where the messages the bot handles come from.
+#[async_trait]
+pub trait ChatSource: Send {
+    /// The next message, `None` once the chat is closed.
+    async fn recv(&mut self) -> Option<ServerMessage>;
+}
+
+#[async_trait]
+impl ChatSink for TwitchIRCClient<SecureTCPTransport, BotCredentials> {
+    async fn say(&self, channel: &str, text: String) -> Result<(), String> {
+        TwitchIRCClient::say(self, channel.to_owned(), text)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn reply(&self, channel: &str, reply_to: &str, text: String) -> Result<(), String> {
+        self.say_in_reply_to(&(channel, reply_to), text)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    fn join(&self, channel: &str) -> Result<(), String> {
+        TwitchIRCClient::join(self, channel.to_owned()).map_err(|e| e.to_string())
+    }
+
+    fn part(&self, channel: &str) {
+        TwitchIRCClient::part(self, channel.to_owned());
+    }
+}
+
+#[async_trait]
+impl ChatSource for mpsc::UnboundedReceiver<ServerMessage> {
+    async fn recv(&mut self) -> Option<ServerMessage> {
+        mpsc::UnboundedReceiver::recv(self).await
+    }
+}
+
+/// A chat message as Twitch would have delivered it, from `user` to
+/// `channel`.
+pub fn privmsg(user: &str, channel: &str, text: &str, message_id: &str) -> PrivmsgMessage {
+    PrivmsgMessage {
+        channel_login: channel.to_owned(),
+        channel_id: String::new(),
+        message_text: text.to_owned(),
+        is_action: false,
+        sender: TwitchUserBasics {
+            id: String::new(),
+            login: user.to_lowercase(),
+            name: user.to_owned(),
+        },
+        badge_info: Vec::new(),
+        badges: Vec::new(),
+        bits: None,
+        name_color: None,
+        emotes: Vec::new(),
+        message_id: message_id.to_owned(),
+        server_timestamp: chrono::Utc::now(),
+        source: IRCMessage::new_simple(
+            "PRIVMSG".to_owned(),
+            vec![format!("#{}", channel), text.to_owned()],
+        ),
+    }
+}
@@ -0,0 +1,130 @@
+use super::{privmsg, ChatSink, ChatSource};
+
+use async_trait::async_trait;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use twitch_irc::message::ServerMessage;
+
+/// A message the bot sent through a `MockSink`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockMessage {
+    pub channel: String,
+    pub text: String,
+    pub reply_to: Option<String>,
+}
+
+/// In-memory chat for tests: messages sent through it come out of its
+/// `MockSource`, and what the bot says into its `MockSink` can be awaited.
+pub struct MockChat {
+    incoming: mpsc::UnboundedSender<ServerMessage>,
+    outgoing: mpsc::UnboundedReceiver<MockMessage>,
+    joined: Arc<Mutex<BTreeSet<String>>>,
+    sent: usize,
+}
+
+pub struct MockSink {
+    outgoing: mpsc::UnboundedSender<MockMessage>,
+    joined: Arc<Mutex<BTreeSet<String>>>,
+}
+
+pub struct MockSource {
+    incoming: mpsc::UnboundedReceiver<ServerMessage>,
+}
+
+impl MockChat {
+    pub fn new() -> (MockChat, MockSink, MockSource) {
+        let (incoming, incoming_receiver) = mpsc::unbounded_channel();
+        let (outgoing_sender, outgoing) = mpsc::unbounded_channel();
+        let joined = Arc::new(Mutex::new(BTreeSet::new()));
+        let chat = MockChat {
+            incoming,
+            outgoing,
+            joined: joined.clone(),
+            sent: 0,
+        };
+        let sink = MockSink {
+            outgoing: outgoing_sender,
+            joined,
+        };
+        let source = MockSource {
+            incoming: incoming_receiver,
+        };
+        (chat, sink, source)
+    }
+
+    /// Sends `text` from `user` to `channel`, returning the message's id.
+    pub fn send(&mut self, user: &str, channel: &str, text: &str) -> String {
+        self.sent += 1;
+        let message_id = format!("mock-{}", self.sent);
+        let msg = privmsg(user, channel, text, &message_id);
+        self.incoming.send(ServerMessage::Privmsg(msg)).ok();
+        message_id
+    }
+
+    /// The next message the bot sends, `None` if it sends nothing within
+    /// `timeout`.
+    pub async fn next(&mut self, timeout: Duration) -> Option<MockMessage> {
+        tokio::time::timeout(timeout, self.outgoing.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Every message the bot sent so far that wasn't awaited yet.
+    pub fn drain(&mut self) -> Vec<MockMessage> {
+        let mut messages = Vec::new();
+        while let Ok(message) = self.outgoing.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// The channels the bot is in.
+    pub fn joined(&self) -> Vec<String> {
+        let joined = self.joined.lock().unwrap_or_else(PoisonError::into_inner);
+        joined.iter().cloned().collect()
+    }
+}
+
+#[async_trait]
+impl ChatSink for MockSink {
+    async fn say(&self, channel: &str, text: String) -> Result<(), String> {
+        self.outgoing
+            .send(MockMessage {
+                channel: channel.to_owned(),
+                text,
+                reply_to: None,
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    async fn reply(&self, channel: &str, reply_to: &str, text: String) -> Result<(), String> {
+        self.outgoing
+            .send(MockMessage {
+                channel: channel.to_owned(),
+                text,
+                reply_to: Some(reply_to.to_owned()),
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    fn join(&self, channel: &str) -> Result<(), String> {
+        let mut joined = self.joined.lock().unwrap_or_else(PoisonError::into_inner);
+        joined.insert(channel.to_owned());
+        Ok(())
+    }
+
+    fn part(&self, channel: &str) {
+        let mut joined = self.joined.lock().unwrap_or_else(PoisonError::into_inner);
+        joined.remove(channel);
+    }
+}
+
+#[async_trait]
+impl ChatSource for MockSource {
+    async fn recv(&mut self) -> Option<ServerMessage> {
+        self.incoming.recv().await
+    }
+}
@@ -0,0 +1,129 @@
+use crate::compression::{compress_vods, Compression};
+use crate::config::Config;
+use crate::generation::generate as generate_one;
+use crate::learn_filter;
+use crate::models::{ModelKind, Models};
+
+use log::info;
+use markov_strings::InputData;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// `sglypa train`: trains every model from the vods and writes the snapshot.
+pub fn train(config: &Config) -> Result<(), String> {
+    let start = Instant::now();
+    let mut models = Models::default();
+    models.train_main(
+        &config.vods_dir,
+        &config.streamers,
+        config.personal,
+        Some(learn_filter),
+        config.strict_vods,
+        &config.generation,
+    )?;
+    for (corpus, streamers) in config.corpus_streamers() {
+        models.train_corpus(
+            &corpus,
+            &config.vods_dir,
+            &streamers,
+            Some(learn_filter),
+            config.strict_vods,
+            &config.generation,
+        )?;
+    }
+    models.save(&config.snapshot)?;
+    info!(
+        "saved snapshot {} in {:?}",
+        config.snapshot.display(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// `sglypa generate`: prints `count` messages of the model called `model`,
+/// each with its score and number of references.
+pub fn generate(config: &Config, model: &str, count: usize) -> Result<(), String> {
+    let models = Models::load(&config.snapshot, &config.generation)?;
+    let kind = ModelKind::parse(model);
+    let markov = models
+        .get(&kind)
+        .ok_or_else(|| format!("no model {} in {}", kind, config.snapshot.display()))?;
+    for _ in 0..count {
+        let deadline = Instant::now() + Duration::from_millis(config.generation.timeout_ms);
+        match generate_one(markov, &config.generation, &kind, deadline) {
+            Ok(result) if config.generation.show_refs => println!(
+                "[{} {}] {} [{}]",
+                result.score,
+                result.refs.len(),
+                result.text,
+                models.authors(&kind, &result).join(",")
+            ),
+            Ok(result) => println!("[{} {}] {}", result.score, result.refs.len(), result.text),
+            Err(e) => println!("{:?}", e),
+        }
+    }
+    Ok(())
+}
+
+/// `sglypa stats`: prints the size of every corpus in the snapshot and the
+/// chatters with the most messages.
+pub fn stats(config: &Config) -> Result<(), String> {
+    let models = Models::load(&config.snapshot, &config.generation)?;
+    if let Some(train_data) = &models.train_data {
+        print_corpus("main", train_data);
+    }
+    for (corpus, train_data) in sorted(&models.corpus_train_data) {
+        print_corpus(
+            &ModelKind::Corpus(corpus.to_owned()).to_string(),
+            train_data,
+        );
+    }
+    for (channel, train_data) in sorted(&models.live_train_data) {
+        print_corpus(&ModelKind::Live(channel.to_owned()).to_string(), train_data);
+    }
+    if let Some(ntrain_data) = &models.ntrain_data {
+        print_corpus("combined", ntrain_data);
+    }
+    if let Some(personal_train_data) = &models.personal_train_data {
+        println!("{} personal models", personal_train_data.len());
+        let mut top = personal_train_data
+            .iter()
+            .map(|(name, data)| (data.len(), name))
+            .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.cmp(a));
+        for (messages, name) in top.iter().take(20) {
+            println!("  {}: {} messages", name, messages);
+        }
+    }
+    Ok(())
+}
+
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+fn print_corpus(name: &str, data: &[InputData]) {
+    let chatters = data
+        .iter()
+        .filter_map(|d| d.meta.as_ref())
+        .collect::<HashSet<_>>();
+    let words = data
+        .iter()
+        .map(|d| d.text.split_whitespace().count())
+        .sum::<usize>();
+    println!(
+        "{}: {} messages from {} chatters, {:.1} words per message",
+        name,
+        data.len(),
+        chatters.len(),
+        words as f64 / data.len().max(1) as f64
+    );
+}
+
+/// `sglypa compress`: replaces the chat logs under `vods_dir` with
+/// compressed ones, which are read just the same.
+pub fn compress(config: &Config, format: Compression, level: Option<i32>) -> Result<(), String> {
+    compress_vods(&config.vods_dir, format, level)
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Who may run a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Anyone while replying is on, moderators and owners always
+    Everyone,
+    Moderator,
+    Owner,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A single word
+    Word,
+    /// A single non-negative number
+    Number,
+    /// Everything that is left, joined by spaces
+    Rest,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+}
+
+const fn arg(name: &'static str, kind: ArgKind) -> ArgSpec {
+    ArgSpec {
+        name,
+        kind,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str, kind: ArgKind) -> ArgSpec {
+    ArgSpec {
+        name,
+        kind,
+        required: false,
+    }
+}
+
+/// What to run once a command matched, see `Sglypa::execute`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Stair,
+    StairTo,
+    On,
+    Off,
+    Join,
+    Leave,
+    ListMods,
+    ListOwners,
+    AddMod,
+    RemMod,
+    Reset,
+    Save,
+    Settings,
+    Info,
+    Sglypa,
+    Nglypa,
+    Diag,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static [ArgSpec],
+    pub role: Role,
+    pub action: Action,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "!st",
+        aliases: &[],
+        args: &[arg("length", ArgKind::Number), arg("text", ArgKind::Rest)],
+        role: Role::Moderator,
+        action: Action::Stair,
+    },
+    Command {
+        name: "!<>",
+        aliases: &[],
+        args: &[
+            arg("channel", ArgKind::Word),
+            arg("length", ArgKind::Number),
+            arg("text", ArgKind::Rest),
+        ],
+        role: Role::Owner,
+        action: Action::StairTo,
+    },
+    Command {
+        name: "!on",
+        aliases: &[],
+        args: &[],
+        role: Role::Moderator,
+        action: Action::On,
+    },
+    Command {
+        name: "!off",
+        aliases: &[],
+        args: &[],
+        role: Role::Moderator,
+        action: Action::Off,
+    },
+    Command {
+        name: "!join",
+        aliases: &[],
+        args: &[arg("channel", ArgKind::Word)],
+        role: Role::Owner,
+        action: Action::Join,
+    },
+    Command {
+        name: "!leave",
+        aliases: &[],
+        args: &[arg("channel", ArgKind::Word)],
+        role: Role::Owner,
+        action: Action::Leave,
+    },
+    Command {
+        name: "!listmods",
+        aliases: &[],
+        args: &[],
+        role: Role::Everyone,
+        action: Action::ListMods,
+    },
+    Command {
+        name: "!listowners",
+        aliases: &[],
+        args: &[],
+        role: Role::Everyone,
+        action: Action::ListOwners,
+    },
+    Command {
+        name: "!addmod",
+        aliases: &[],
+        args: &[arg("name", ArgKind::Word)],
+        role: Role::Owner,
+        action: Action::AddMod,
+    },
+    Command {
+        name: "!remmod",
+        aliases: &[],
+        args: &[arg("name", ArgKind::Word)],
+        role: Role::Owner,
+        action: Action::RemMod,
+    },
+    Command {
+        name: "!reset",
+        aliases: &[],
+        args: &[optional("all", ArgKind::Word)],
+        role: Role::Owner,
+        action: Action::Reset,
+    },
+    Command {
+        name: "!save",
+        aliases: &[],
+        args: &[],
+        role: Role::Owner,
+        action: Action::Save,
+    },
+    Command {
+        name: "!settings",
+        aliases: &[],
+        args: &[optional("settings", ArgKind::Rest)],
+        role: Role::Owner,
+        action: Action::Settings,
+    },
+    Command {
+        name: "!info",
+        aliases: &[],
+        args: &[optional("name", ArgKind::Word)],
+        role: Role::Everyone,
+        action: Action::Info,
+    },
+    Command {
+        name: "!sglypa",
+        aliases: &[],
+        args: &[],
+        role: Role::Everyone,
+        action: Action::Sglypa,
+    },
+    Command {
+        name: "!nglypa",
+        aliases: &[],
+        args: &[optional("all", ArgKind::Word)],
+        role: Role::Everyone,
+        action: Action::Nglypa,
+    },
+    Command {
+        name: "!diag",
+        aliases: &[],
+        args: &[optional("model", ArgKind::Word)],
+        role: Role::Owner,
+        action: Action::Diag,
+    },
+];
+
+impl Command {
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_owned();
+        for spec in self.args {
+            let name = match spec.kind {
+                ArgKind::Rest => format!("{}...", spec.name),
+                _ => spec.name.to_owned(),
+            };
+            if spec.required {
+                usage += &format!(" <{}>", name);
+            } else {
+                usage += &format!(" [{}]", name);
+            }
+        }
+        usage
+    }
+
+    /// Parses the words after the command name according to `self.args`.
+    /// Words beyond the schema are ignored.
+    pub fn parse_args(&self, words: &[&str]) -> Result<CommandArgs, ArgError> {
+        let mut values = HashMap::new();
+        let mut words = words.iter();
+        for spec in self.args {
+            let value = match spec.kind {
+                ArgKind::Rest => {
+                    let rest = words.by_ref().copied().collect::<Vec<_>>().join(" ");
+                    (!rest.is_empty()).then_some(rest)
+                }
+                _ => words.next().map(|w| w.to_string()),
+            };
+            let Some(value) = value else {
+                if spec.required {
+                    return Err(ArgError::Missing(spec.name));
+                }
+                continue;
+            };
+            if spec.kind == ArgKind::Number && value.parse::<usize>().is_err() {
+                return Err(ArgError::NotANumber(spec.name, value));
+            }
+            values.insert(spec.name, value);
+        }
+        Ok(CommandArgs { values })
+    }
+}
+
+/// Splits a chat message into the lowercased command name and the words
+/// after it, or `None` if it isn't a command. Drops the invisible tag
+/// characters some chat clients append to bypass duplicate detection.
+/// `!<>` may be glued to its channel, as in `!<>channel 3 text`.
+pub fn tokenize(text: &str) -> Option<(String, Vec<&str>)> {
+    let mut words = text
+        .split_whitespace()
+        .map(|w| w.trim_matches('\u{E0000}'))
+        .filter(|w| !w.is_empty());
+    let name = words.next()?;
+    if !name.starts_with('!') {
+        return None;
+    }
+    if let Some(channel) = name.strip_prefix("!<>").filter(|c| !c.is_empty()) {
+        return Some((
+            "!<>".to_owned(),
+            std::iter::once(channel).chain(words).collect(),
+        ));
+    }
+    Some((name.to_lowercase(), words.collect()))
+}
+
+/// Finds the command called `name` (by name or alias).
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|c| c.name == name || c.aliases.contains(&name))
+}
+
+#[derive(Debug)]
+pub struct CommandArgs {
+    values: HashMap<&'static str, String>,
+}
+
+impl CommandArgs {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|s| s.as_str())
+    }
+
+    /// Returns a `Number` argument; it was already validated by `parse_args`.
+    pub fn number(&self, name: &str) -> Option<usize> {
+        self.get(name).and_then(|v| v.parse().ok())
+    }
+}
+
+#[derive(Debug)]
+pub enum ArgError {
+    Missing(&'static str),
+    NotANumber(&'static str, String),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Missing(name) => write!(f, "missing {}", name),
+            ArgError::NotANumber(name, value) => {
+                write!(f, "{} must be a number, got {}", name, value)
+            }
+        }
+    }
+}
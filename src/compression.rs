@@ -0,0 +1,128 @@
+use crate::files::write_atomically;
+use crate::importers::{Manifest, MANIFEST};
+
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::info;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// The compression of `path` judging by its extension.
+    pub fn of(path: &Path) -> Option<Compression> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Opens `path` for reading, decompressing it on the fly if it ends in
+/// `.gz` or `.zst`.
+pub fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(match Compression::of(path) {
+        Some(Compression::Gzip) => Box::new(BufReader::new(GzDecoder::new(file))),
+        Some(Compression::Zstd) => Box::new(BufReader::new(zstd::Decoder::with_buffer(file)?)),
+        None => Box::new(file),
+    })
+}
+
+/// Replaces every chat log in the streamer folders under `vods_dir` that
+/// isn't compressed yet with a compressed `.gz` or `.zst` copy, at the
+/// format's default level unless `level` is given. What counts as a chat log
+/// goes by the folder's manifest and the extensions, as for training.
+pub fn compress_vods(
+    vods_dir: &Path,
+    compression: Compression,
+    level: Option<i32>,
+) -> Result<(), String> {
+    let read_dir = |dir: &Path| {
+        fs::read_dir(dir).map_err(|e| format!("failed to read {}: {}", dir.display(), e))
+    };
+    let (mut before, mut after) = (0, 0);
+    for streamer in read_dir(vods_dir)? {
+        let streamer = streamer.map_err(|e| e.to_string())?.path();
+        if !streamer.is_dir() {
+            continue;
+        }
+        let manifest = Manifest::load(&streamer).map_err(|e| {
+            format!(
+                "failed to read {}: {}",
+                streamer.join(MANIFEST).display(),
+                e
+            )
+        })?;
+        let mut paths = Vec::new();
+        for vod in read_dir(&streamer)? {
+            let vod = vod.map_err(|e| e.to_string())?;
+            let path = vod.path();
+            if vod.file_name() != MANIFEST
+                && path.is_file()
+                && Compression::of(&path).is_none()
+                && manifest.format(&path).is_some()
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            let target = compress(&path, compression, level)
+                .map_err(|e| format!("failed to compress {}: {}", path.display(), e))?;
+            let size = |path: &Path| fs::metadata(path).map_or(0, |m| m.len());
+            let (from, to) = (size(&path), size(&target));
+            fs::remove_file(&path)
+                .map_err(|e| format!("failed to remove {}: {}", path.display(), e))?;
+            info!("{}: {} -> {} bytes", target.display(), from, to);
+            before += from;
+            after += to;
+        }
+    }
+    info!("compressed {} bytes of vods to {} bytes", before, after);
+    Ok(())
+}
+
+/// Writes the compressed copy of `path` next to it, returning its path.
+fn compress(path: &Path, compression: Compression, level: Option<i32>) -> io::Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(compression.extension());
+    let target = path.with_file_name(name);
+    let mut input = BufReader::new(File::open(path)?);
+    write_atomically(&target, false, |output| -> io::Result<()> {
+        match compression {
+            Compression::Gzip => {
+                let level = level.map_or(flate2::Compression::default(), |level| {
+                    flate2::Compression::new(level.clamp(0, 9) as u32)
+                });
+                let mut encoder = GzEncoder::new(output, level);
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+            Compression::Zstd => {
+                let level = level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL);
+                let mut encoder = zstd::Encoder::new(output, level)?;
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(target)
+}
@@ -0,0 +1,456 @@
+use crate::compression::Compression;
+use crate::cooldown::CooldownConfig;
+use crate::generation::GenerationConfig;
+use crate::models::corpus_name;
+use crate::outbound::OutboundConfig;
+use crate::pool::PoolConfig;
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+const DEFAULT_CONFIG_PATH: &str = "sglypa.toml";
+const DEFAULT_SECRETS_PATH: &str = "secrets.toml";
+
+// Every flag can also be given through the matching `SGLYPA_*` environment
+// variable and overrides the config file.
+/// Markov chain chat bot for Twitch
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// Path to the TOML config file
+    #[arg(short, long, env = "SGLYPA_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Twitch login of the bot account
+    #[arg(long, env = "SGLYPA_LOGIN", global = true)]
+    pub login: Option<String>,
+    /// OAuth token of the bot account
+    #[arg(long, env = "SGLYPA_TOKEN", hide_env_values = true, global = true)]
+    pub token: Option<String>,
+    /// TOML file with `token`, `refresh_token`, `client_id` and `client_secret`
+    #[arg(long, env = "SGLYPA_SECRETS_FILE", global = true)]
+    pub secrets_file: Option<PathBuf>,
+    /// File to keep refreshed tokens in, enables token refreshing
+    #[arg(long, env = "SGLYPA_TOKEN_FILE", global = true)]
+    pub token_file: Option<PathBuf>,
+    /// Client id of the Twitch application, used for token refreshing
+    #[arg(long, env = "SGLYPA_CLIENT_ID", global = true)]
+    pub client_id: Option<String>,
+    /// Client secret of the Twitch application, used for token refreshing
+    #[arg(
+        long,
+        env = "SGLYPA_CLIENT_SECRET",
+        hide_env_values = true,
+        global = true
+    )]
+    pub client_secret: Option<String>,
+    /// Channel to join on startup, can be repeated
+    #[arg(
+        long = "channel",
+        env = "SGLYPA_CHANNELS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub channels: Vec<String>,
+    /// Directory with one folder of chat logs per streamer
+    #[arg(long, env = "SGLYPA_VODS_DIR", global = true)]
+    pub vods_dir: Option<PathBuf>,
+    /// Streamer folder to train on, can be repeated
+    #[arg(
+        long = "streamer",
+        env = "SGLYPA_STREAMERS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub streamers: Vec<String>,
+    /// Snapshot file of the trained models
+    #[arg(long, env = "SGLYPA_SNAPSHOT", global = true)]
+    pub snapshot: Option<PathBuf>,
+    /// JSON file remembering moderators and joined channels
+    #[arg(long, env = "SGLYPA_STATE_FILE", global = true)]
+    pub state_file: Option<PathBuf>,
+    /// Retrain from the vods even if a snapshot exists
+    #[arg(long, global = true)]
+    pub retrain: bool,
+    /// Fail training if a chat log can't be read instead of skipping it
+    #[arg(long, global = true)]
+    pub strict: bool,
+    /// Generation setting as KEY=VALUE (same keys as `!settings`)
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    pub settings: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CliCommand {
+    /// Connect to Twitch and chat (the default)
+    Run,
+    /// Train every model from the vods and write a fresh snapshot
+    Train,
+    /// Print messages generated from the snapshot, e.g. to try out `--set`
+    Generate {
+        /// main, combined, #<channel>, vods:<corpus> or a chatter's name
+        #[arg(short, long, default_value = "main")]
+        model: String,
+        /// How many messages to print
+        #[arg(short = 'n', long, default_value_t = 5)]
+        count: usize,
+    },
+    /// Print statistics about the corpora in the snapshot
+    Stats,
+    /// Compress the chat logs under the vods directory in place
+    Compress {
+        #[arg(long, value_enum, default_value_t = Compression::Zstd)]
+        format: Compression,
+        /// Compression level, the format's default if not given
+        #[arg(long)]
+        level: Option<i32>,
+    },
+    /// Chat with the bot in the terminal instead of on Twitch, without saving
+    /// its state file or snapshot
+    Console {
+        /// Who the lines typed in are from, the bot's login by default
+        #[arg(short, long)]
+        user: Option<String>,
+        /// Channel the lines typed in are sent to
+        #[arg(long, default_value = "console")]
+        to: String,
+    },
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Credentials {
+    pub login: String,
+    /// Prefer the secrets file or `SGLYPA_TOKEN` over putting these here
+    pub token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Where refreshed tokens are kept; tokens are only refreshed if set
+    pub token_file: Option<PathBuf>,
+    pub secrets_file: Option<PathBuf>,
+}
+
+/// Contents of the secrets file, kept apart from the config so the config
+/// can be shared or committed.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Secrets {
+    token: Option<String>,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+/// Reads `path`, or the default path if none was given and it exists.
+fn read_optional(path: Option<PathBuf>, default: &str) -> Result<Option<String>, String> {
+    let path = path.or_else(|| {
+        let default = PathBuf::from(default);
+        default.exists().then_some(default)
+    });
+    match path {
+        Some(path) => fs::read_to_string(&path)
+            .map(Some)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e)),
+        None => Ok(None),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub twitch: Credentials,
+    /// Channels joined on startup
+    pub channels: Vec<String>,
+    pub owners: Vec<String>,
+    pub moderators: Vec<String>,
+    pub vods_dir: PathBuf,
+    /// Streamer folders under `vods_dir` to train on, all of them if empty
+    pub streamers: Vec<String>,
+    /// Fail training if a chat log can't be read instead of skipping it
+    pub strict_vods: bool,
+    /// Streamer folders whose chat `!sglypa` speaks like, by channel.
+    /// Other channels use the model trained on `streamers`
+    pub corpora: HashMap<String, Vec<String>>,
+    /// Also build a model per chatter for `!<name>`
+    pub personal: bool,
+    /// Moderators and channels changed through chat are remembered here
+    pub state_file: PathBuf,
+    /// Models are loaded from here instead of retraining if it exists
+    pub snapshot: PathBuf,
+    /// Seconds between saving the snapshot, 0 to only save on `!save`
+    pub autosave_secs: u64,
+    /// Besides a live model per channel, also learn one from every
+    /// channel's chat together
+    pub combined_live: bool,
+    pub cooldowns: CooldownConfig,
+    pub outbound: OutboundConfig,
+    pub pool: PoolConfig,
+    /// Whether the bot answers everyone, until `!on` or `!off`
+    pub replying: bool,
+    pub generation: GenerationConfig,
+    /// Overrides of `replying` and `generation` by channel
+    pub profiles: HashMap<String, ChannelProfile>,
+}
+
+/// Settings of one channel that differ from the global ones.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelProfile {
+    pub replying: Option<bool>,
+    pub a_till_proc: Option<usize>,
+    pub min_len: Option<usize>,
+    pub max_len: Option<usize>,
+    pub show_refs: Option<bool>,
+}
+
+impl ChannelProfile {
+    /// `generation` with this profile's overrides.
+    pub fn apply(&self, generation: &GenerationConfig) -> GenerationConfig {
+        GenerationConfig {
+            a_till_proc: self.a_till_proc.unwrap_or(generation.a_till_proc),
+            min_len: self.min_len.unwrap_or(generation.min_len),
+            max_len: self.max_len.unwrap_or(generation.max_len),
+            show_refs: self.show_refs.unwrap_or(generation.show_refs),
+            ..generation.clone()
+        }
+    }
+}
+
+/// Checks that every profile in `profiles` makes valid settings out of
+/// `generation`, naming the first one that doesn't.
+pub fn validate_profiles(
+    profiles: &HashMap<String, ChannelProfile>,
+    generation: &GenerationConfig,
+) -> Result<(), String> {
+    let mut channels = profiles.keys().collect::<Vec<_>>();
+    channels.sort();
+    for channel in channels {
+        profiles[channel]
+            .apply(generation)
+            .validate()
+            .map_err(|e| format!("profile {}: {}", channel, e))?;
+    }
+    Ok(())
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            twitch: Credentials::default(),
+            channels: Vec::new(),
+            owners: Vec::new(),
+            moderators: Vec::new(),
+            vods_dir: PathBuf::from("./vods"),
+            streamers: Vec::new(),
+            strict_vods: false,
+            corpora: HashMap::new(),
+            personal: true,
+            state_file: PathBuf::from("sglypa.state.json"),
+            snapshot: PathBuf::from("sglypa.snapshot"),
+            autosave_secs: 600,
+            combined_live: false,
+            cooldowns: CooldownConfig::default(),
+            outbound: OutboundConfig::default(),
+            pool: PoolConfig::default(),
+            replying: false,
+            generation: GenerationConfig::default(),
+            profiles: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file named by `args` (or `sglypa.toml` if it
+    /// exists) and applies the command-line and environment overrides.
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let mut config: Config = match read_optional(args.config.clone(), DEFAULT_CONFIG_PATH)? {
+            Some(text) => {
+                toml::from_str(&text).map_err(|e| format!("failed to parse config: {}", e))?
+            }
+            None => Config::default(),
+        };
+
+        let secrets_file = args
+            .secrets_file
+            .clone()
+            .or_else(|| config.twitch.secrets_file.clone());
+        if let Some(text) = read_optional(secrets_file, DEFAULT_SECRETS_PATH)? {
+            let secrets: Secrets =
+                toml::from_str(&text).map_err(|e| format!("failed to parse secrets: {}", e))?;
+            let twitch = &mut config.twitch;
+            twitch.token = secrets.token.or(twitch.token.take());
+            twitch.refresh_token = secrets.refresh_token.or(twitch.refresh_token.take());
+            twitch.client_id = secrets.client_id.or(twitch.client_id.take());
+            twitch.client_secret = secrets.client_secret.or(twitch.client_secret.take());
+        }
+
+        if let Some(login) = &args.login {
+            config.twitch.login = login.to_owned();
+        }
+        if let Some(token) = &args.token {
+            config.twitch.token = Some(token.to_owned());
+        }
+        if let Some(token_file) = &args.token_file {
+            config.twitch.token_file = Some(token_file.to_owned());
+        }
+        if let Some(client_id) = &args.client_id {
+            config.twitch.client_id = Some(client_id.to_owned());
+        }
+        if let Some(client_secret) = &args.client_secret {
+            config.twitch.client_secret = Some(client_secret.to_owned());
+        }
+        if !args.channels.is_empty() {
+            config.channels = args.channels.clone();
+        }
+        if let Some(vods_dir) = &args.vods_dir {
+            config.vods_dir = vods_dir.to_owned();
+        }
+        if let Some(state_file) = &args.state_file {
+            config.state_file = state_file.to_owned();
+        }
+        if let Some(snapshot) = &args.snapshot {
+            config.snapshot = snapshot.to_owned();
+        }
+        if !args.streamers.is_empty() {
+            config.streamers = args.streamers.clone();
+        }
+        if args.strict {
+            config.strict_vods = true;
+        }
+        for setting in args.settings.iter() {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected KEY=VALUE, got {}", setting))?;
+            config
+                .generation
+                .set(key.trim(), value.trim())
+                .map_err(|e| e.to_string())?;
+        }
+
+        config.twitch.login = config.twitch.login.to_lowercase();
+        config.generation.validate().map_err(|e| e.to_string())?;
+        config.corpora = config
+            .corpora
+            .into_iter()
+            .map(|(channel, streamers)| (channel.to_lowercase(), streamers))
+            .collect();
+        config.profiles = config
+            .profiles
+            .into_iter()
+            .map(|(channel, profile)| (channel.to_lowercase(), profile))
+            .collect();
+        validate_profiles(&config.profiles, &config.generation)?;
+        Ok(config)
+    }
+
+    /// Streamer folders of every corpus in `corpora`, by corpus name.
+    pub fn corpus_streamers(&self) -> BTreeMap<String, Vec<String>> {
+        self.corpora
+            .values()
+            .map(|streamers| (corpus_name(streamers), streamers.to_owned()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::ScratchDir;
+    use std::path::Path;
+
+    /// Loads the config with `args` after the program name, and `config`
+    /// and `secrets` as the config and secrets files.
+    fn load(dir: &Path, config: &str, secrets: &str, args: &[&str]) -> Result<Config, String> {
+        let config_path = dir.join("sglypa.toml");
+        let secrets_path = dir.join("secrets.toml");
+        fs::write(&config_path, config).unwrap();
+        fs::write(&secrets_path, secrets).unwrap();
+        let files = [
+            "--config",
+            config_path.to_str().unwrap(),
+            "--secrets-file",
+            secrets_path.to_str().unwrap(),
+        ];
+        let args = ["sglypa"].iter().chain(&files).chain(args);
+        Config::load(&Args::try_parse_from(args).unwrap())
+    }
+
+    #[test]
+    fn secrets_override_the_file_and_flags_override_both() {
+        let dir = ScratchDir::new("config-precedence");
+        let config = r#"
+            [twitch]
+            login = "FileBot"
+            token = "file"
+            refresh_token = "file"
+            client_id = "file"
+        "#;
+        let secrets = r#"
+            token = "secret"
+            client_id = "secret"
+        "#;
+
+        let loaded = load(&dir, config, secrets, &[]).unwrap();
+        let twitch = &loaded.twitch;
+        assert_eq!(twitch.login, "filebot");
+        assert_eq!(twitch.token.as_deref(), Some("secret"));
+        assert_eq!(twitch.refresh_token.as_deref(), Some("file"));
+        assert_eq!(twitch.client_id.as_deref(), Some("secret"));
+
+        let args = ["--login", "FlagBot", "--token", "flag", "--channel", "a,b"];
+        let loaded = load(&dir, config, secrets, &args).unwrap();
+        let twitch = &loaded.twitch;
+        assert_eq!(twitch.login, "flagbot");
+        assert_eq!(twitch.token.as_deref(), Some("flag"));
+        assert_eq!(twitch.client_id.as_deref(), Some("secret"));
+        assert_eq!(loaded.channels, ["a", "b"]);
+    }
+
+    #[test]
+    fn profile_and_corpus_channels_are_lowercased() {
+        let dir = ScratchDir::new("config-lowercase");
+        let config = r#"
+            [corpora]
+            MyChannel = ["streamer"]
+
+            [profiles.MyChannel]
+            min_len = 150
+        "#;
+        let loaded = load(&dir, config, "", &[]).unwrap();
+        assert_eq!(loaded.corpora["mychannel"], ["streamer"]);
+        assert_eq!(loaded.profiles["mychannel"].min_len, Some(150));
+        assert!(!loaded.corpora.contains_key("MyChannel"));
+        assert!(!loaded.profiles.contains_key("MyChannel"));
+    }
+
+    #[test]
+    fn settings_flags_are_checked() {
+        let dir = ScratchDir::new("config-set");
+        let error = |setting| load(&dir, "", "", &["--set", setting]).unwrap_err();
+        assert_eq!(error("MIN_LEN"), "expected KEY=VALUE, got MIN_LEN");
+        assert_eq!(error("NOPE=1"), "unknown setting NOPE");
+        assert_eq!(error("MIN_LEN=abc"), "invalid value abc for MIN_LEN");
+        assert!(error("MIN_LEN=1").contains("MIN_LEN <= MAX_LEN"));
+
+        let loaded = load(&dir, "", "", &["--set", "MIN_LEN = 30", "--set", "PROC=5"]).unwrap();
+        assert_eq!(loaded.generation.min_len, 30);
+        assert_eq!(loaded.generation.a_till_proc, 5);
+    }
+
+    #[test]
+    fn profiles_must_suit_the_settings() {
+        let dir = ScratchDir::new("config-profiles");
+        let config = r#"
+            [profiles.chan]
+            max_len = 100
+        "#;
+        let error = load(&dir, config, "", &[]).unwrap_err();
+        assert!(error.starts_with("profile chan: "), "{}", error);
+        load(&dir, config, "", &["--set", "MIN_LEN=50"]).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::chat::{privmsg, ChatSink, ChatSource};
+use crate::config::{Args, Config};
+use crate::outbound::OutboundConfig;
+use crate::{prepare_models, Sglypa};
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+use twitch_irc::message::ServerMessage;
+
+/// Prints what the bot says.
+struct ConsoleSink;
+
+/// Chat messages typed into stdin. Lines starting with `/user` or
+/// `/channel` change who is talking where.
+struct ConsoleSource {
+    lines: Lines<BufReader<Stdin>>,
+    user: String,
+    channel: String,
+    count: u64,
+}
+
+/// `sglypa console`: handles the lines read from stdin like messages from
+/// Twitch and prints what the bot says.
+pub async fn run(
+    args: &Args,
+    config: &Config,
+    user: Option<String>,
+    channel: String,
+) -> Result<(), String> {
+    let mut user = user
+        .unwrap_or_else(|| config.twitch.login.to_owned())
+        .to_lowercase();
+    if user.is_empty() {
+        user = "console".to_owned();
+    }
+    let channel = channel.to_lowercase();
+    println!(
+        "Chatting as {} in #{}, /user NAME or /channel NAME to switch, /quit to stop",
+        user, channel
+    );
+    let source = ConsoleSource {
+        lines: BufReader::new(tokio::io::stdin()).lines(),
+        user,
+        channel,
+        count: 0,
+    };
+
+    let mut sglypa = Sglypa::new(
+        config,
+        Arc::new(ConsoleSink),
+        Box::new(source),
+        OutboundConfig::unlimited(),
+    )?;
+    // Trying out `!join` or `!addmod` here shouldn't change what the bot
+    // does on Twitch, nor should what it learns here end up in its snapshot
+    sglypa.state_file = None;
+    sglypa.read_only_snapshot = true;
+    sglypa.autosave = None;
+    prepare_models(&mut sglypa, args, config)?;
+    sglypa.prepare_combined();
+    sglypa.run().await;
+    // Let replies still being generated for piped in lines print
+    sglypa.finish().await;
+    Ok(())
+}
+
+#[async_trait]
+impl ChatSink for ConsoleSink {
+    async fn say(&self, channel: &str, text: String) -> Result<(), String> {
+        println!("#{} > {}", channel, text);
+        Ok(())
+    }
+
+    async fn reply(&self, channel: &str, _reply_to: &str, text: String) -> Result<(), String> {
+        println!("#{} > {}", channel, text);
+        Ok(())
+    }
+
+    fn join(&self, _channel: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn part(&self, _channel: &str) {}
+}
+
+#[async_trait]
+impl ChatSource for ConsoleSource {
+    async fn recv(&mut self) -> Option<ServerMessage> {
+        loop {
+            let line = match self.lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => {
+                    log::error!("failed to read stdin: {}", e);
+                    return None;
+                }
+            };
+            let line = line.trim();
+            match line.split_once(' ') {
+                _ if line.is_empty() => continue,
+                _ if line == "/quit" => return None,
+                Some(("/user", name)) => self.user = name.trim().to_lowercase(),
+                Some(("/channel", name)) => {
+                    self.channel = name.trim().trim_start_matches('#').to_lowercase()
+                }
+                _ => {
+                    self.count += 1;
+                    let message_id = format!("console-{}", self.count);
+                    let msg = privmsg(&self.user, &self.channel, line, &message_id);
+                    return Some(ServerMessage::Privmsg(msg));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Key used for the `!<name>` personal model commands.
+pub const PERSONAL: &str = "!personal";
+
+/// Only prune the bookkeeping maps once they grow past this.
+const PRUNE_THRESHOLD: usize = 4096;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cooldown {
+    /// Seconds between two uses of the command in a channel by anyone
+    pub global_secs: u64,
+    /// Seconds between two uses of the command in a channel by one user
+    pub user_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CooldownConfig {
+    /// Tell users a command is on cooldown instead of silently ignoring it
+    pub reply: bool,
+    /// Moderators and owners are never rate limited
+    pub exempt_moderators: bool,
+    /// Cooldown of every command without an override
+    pub default: Cooldown,
+    /// Overrides by command name, e.g. `"!sglypa"`, or `"!personal"` for `!<name>`
+    pub commands: HashMap<String, Cooldown>,
+    /// Overrides by channel and then command name
+    pub channels: HashMap<String, HashMap<String, Cooldown>>,
+}
+
+impl Default for CooldownConfig {
+    fn default() -> Self {
+        Self {
+            reply: false,
+            exempt_moderators: true,
+            default: Cooldown {
+                global_secs: 5,
+                user_secs: 30,
+            },
+            commands: HashMap::new(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+/// Remembers when commands were last used and decides whether they may run
+/// again.
+pub struct Cooldowns {
+    pub config: CooldownConfig,
+    last_global: HashMap<(String, String), Instant>,
+    last_user: HashMap<(String, String, String), Instant>,
+    /// Until when a user was already told about a cooldown
+    warned: HashMap<(String, String, String), Instant>,
+}
+
+/// Outcome of `Cooldowns::check`.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allowed,
+    /// On cooldown for this much longer, the user should be told
+    Notify(Duration),
+    /// On cooldown, drop silently
+    Drop,
+}
+
+impl Cooldowns {
+    pub fn new(mut config: CooldownConfig) -> Self {
+        config.commands = normalize(config.commands);
+        config.channels = config
+            .channels
+            .into_iter()
+            .map(|(channel, commands)| (channel.to_lowercase(), normalize(commands)))
+            .collect();
+        Self {
+            config,
+            last_global: HashMap::new(),
+            last_user: HashMap::new(),
+            warned: HashMap::new(),
+        }
+    }
+
+    pub fn cooldown(&self, channel: &str, command: &str) -> Cooldown {
+        self.config
+            .channels
+            .get(channel)
+            .and_then(|commands| commands.get(command))
+            .or_else(|| self.config.commands.get(command))
+            .copied()
+            .unwrap_or(self.config.default)
+    }
+
+    /// Checks whether `user` may run `command` in `channel` now, and records
+    /// the use if so.
+    pub fn check(&mut self, channel: &str, command: &str, user: &str) -> Verdict {
+        self.check_at(channel, command, user, Instant::now())
+    }
+
+    fn check_at(&mut self, channel: &str, command: &str, user: &str, now: Instant) -> Verdict {
+        let cooldown = self.cooldown(channel, command);
+        let global_key = (channel.to_owned(), command.to_owned());
+        let user_key = (channel.to_owned(), command.to_owned(), user.to_owned());
+
+        let since = |last: Option<&Instant>| last.map(|last| now.duration_since(*last));
+        let left = |since: Option<Duration>, secs: u64| {
+            since.and_then(|since| Duration::from_secs(secs).checked_sub(since))
+        };
+        let remaining = left(
+            since(self.last_global.get(&global_key)),
+            cooldown.global_secs,
+        )
+        .max(left(
+            since(self.last_user.get(&user_key)),
+            cooldown.user_secs,
+        ))
+        .filter(|remaining| !remaining.is_zero());
+
+        if let Some(remaining) = remaining {
+            if !self.config.reply || self.warned.get(&user_key).is_some_and(|until| *until > now) {
+                return Verdict::Drop;
+            }
+            self.warned.insert(user_key, now + remaining);
+            return Verdict::Notify(remaining);
+        }
+
+        self.last_global.insert(global_key, now);
+        self.last_user.insert(user_key, now);
+        self.prune(now);
+        Verdict::Allowed
+    }
+
+    /// Forgets uses that are too old to matter for any cooldown.
+    fn prune(&mut self, now: Instant) {
+        if self.last_user.len() + self.warned.len() < PRUNE_THRESHOLD {
+            return;
+        }
+        let longest = self
+            .config
+            .channels
+            .values()
+            .flat_map(|commands| commands.values())
+            .chain(self.config.commands.values())
+            .chain([&self.config.default])
+            .map(|c| c.global_secs.max(c.user_secs))
+            .max()
+            .unwrap_or(0);
+        let longest = Duration::from_secs(longest);
+        self.last_global
+            .retain(|_, last| now.duration_since(*last) < longest);
+        self.last_user
+            .retain(|_, last| now.duration_since(*last) < longest);
+        self.warned.retain(|_, until| *until > now);
+    }
+}
+
+/// Lowercases command names and adds the `!` if it was left out.
+fn normalize(commands: HashMap<String, Cooldown>) -> HashMap<String, Cooldown> {
+    commands
+        .into_iter()
+        .map(|(name, cooldown)| {
+            let name = name.to_lowercase();
+            if name.starts_with('!') {
+                (name, cooldown)
+            } else {
+                (format!("!{}", name), cooldown)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cooldowns(reply: bool, global_secs: u64, user_secs: u64) -> Cooldowns {
+        Cooldowns::new(CooldownConfig {
+            reply,
+            default: Cooldown {
+                global_secs,
+                user_secs,
+            },
+            ..CooldownConfig::default()
+        })
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn global_cooldown_holds_back_everyone() {
+        let mut cooldowns = cooldowns(false, 5, 0);
+        let start = Instant::now();
+        assert_eq!(
+            cooldowns.check_at("chan", "!sglypa", "a", start),
+            Verdict::Allowed
+        );
+        let check = |cooldowns: &mut Cooldowns, user, at| {
+            cooldowns.check_at("chan", "!sglypa", user, start + at)
+        };
+        assert_eq!(check(&mut cooldowns, "b", secs(4)), Verdict::Drop);
+        assert_eq!(
+            cooldowns.check_at("other", "!sglypa", "b", start + secs(4)),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            cooldowns.check_at("chan", "!nglypa", "b", start + secs(4)),
+            Verdict::Allowed
+        );
+        assert_eq!(check(&mut cooldowns, "b", secs(5)), Verdict::Allowed);
+    }
+
+    #[test]
+    fn user_cooldown_only_holds_back_the_user() {
+        let mut cooldowns = cooldowns(false, 1, 30);
+        let start = Instant::now();
+        let mut check = |user, at| cooldowns.check_at("chan", "!sglypa", user, start + at);
+        assert_eq!(check("a", secs(0)), Verdict::Allowed);
+        assert_eq!(check("b", secs(2)), Verdict::Allowed);
+        assert_eq!(check("a", secs(10)), Verdict::Drop);
+        assert_eq!(check("c", secs(10)), Verdict::Allowed);
+        assert_eq!(check("a", secs(30)), Verdict::Allowed);
+    }
+
+    #[test]
+    fn notifies_once_per_cooldown() {
+        let mut cooldowns = cooldowns(true, 0, 30);
+        let start = Instant::now();
+        let mut check = |user, at| cooldowns.check_at("chan", "!sglypa", user, start + at);
+        assert_eq!(check("a", secs(0)), Verdict::Allowed);
+        assert_eq!(check("a", secs(10)), Verdict::Notify(secs(20)));
+        assert_eq!(check("a", secs(11)), Verdict::Drop);
+        assert_eq!(check("a", secs(29)), Verdict::Drop);
+        assert_eq!(check("a", secs(30)), Verdict::Allowed);
+        assert_eq!(check("a", secs(40)), Verdict::Notify(secs(20)));
+    }
+
+    #[test]
+    fn overrides_by_channel_then_command() {
+        let only_global = |global_secs| Cooldown {
+            global_secs,
+            user_secs: 0,
+        };
+        let cooldowns = Cooldowns::new(CooldownConfig {
+            default: only_global(1),
+            commands: HashMap::from([("SGLYPA".to_owned(), only_global(2))]),
+            channels: HashMap::from([(
+                "Chan".to_owned(),
+                HashMap::from([("!sglypa".to_owned(), only_global(3))]),
+            )]),
+            ..CooldownConfig::default()
+        });
+        assert_eq!(cooldowns.cooldown("chan", "!sglypa"), only_global(3));
+        assert_eq!(cooldowns.cooldown("other", "!sglypa"), only_global(2));
+        assert_eq!(cooldowns.cooldown("chan", "!info"), only_global(1));
+    }
+
+    #[test]
+    fn prunes_uses_older_than_every_cooldown() {
+        let mut cooldowns = cooldowns(true, 0, 30);
+        let start = Instant::now();
+        for user in 0..PRUNE_THRESHOLD {
+            let user = user.to_string();
+            assert_eq!(
+                cooldowns.check_at("chan", "!sglypa", &user, start),
+                Verdict::Allowed
+            );
+        }
+        assert_eq!(
+            cooldowns.check_at("chan", "!sglypa", "0", start + secs(1)),
+            Verdict::Notify(secs(29))
+        );
+        assert!(cooldowns.last_user.len() >= PRUNE_THRESHOLD);
+
+        // Still on cooldown, so nothing is forgotten
+        cooldowns.check_at("chan", "!sglypa", "new", start + secs(29));
+        assert_eq!(cooldowns.last_user.len(), PRUNE_THRESHOLD + 1);
+
+        cooldowns.check_at("chan", "!sglypa", "later", start + secs(30));
+        assert_eq!(cooldowns.last_user.len(), 2);
+        assert!(cooldowns.warned.is_empty());
+    }
+}
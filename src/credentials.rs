@@ -0,0 +1,185 @@
+use crate::config::Credentials;
+use crate::files::write_atomically;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use twitch_irc::login::{
+    CredentialsPair, LoginCredentials, RefreshingLoginCredentials, StaticLoginCredentials,
+    TokenStorage, UserAccessToken,
+};
+
+/// Keeps the current `UserAccessToken` as JSON in a local file, so refreshed
+/// tokens survive restarts.
+#[derive(Debug, Clone)]
+pub struct FileTokenStorage {
+    path: PathBuf,
+}
+
+impl FileTokenStorage {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Writes an initial token if the file doesn't exist yet. The token is
+    /// marked as ancient so it gets refreshed on the first connect.
+    pub fn seed(&self, access_token: &str, refresh_token: &str) -> Result<(), String> {
+        if self.path.exists() {
+            return Ok(());
+        }
+        info!("seeding token storage {}", self.path.display());
+        self.write(&UserAccessToken {
+            access_token: access_token.to_owned(),
+            refresh_token: refresh_token.to_owned(),
+            created_at: DateTime::<Utc>::UNIX_EPOCH,
+            expires_at: None,
+        })
+    }
+
+    fn write(&self, token: &UserAccessToken) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(token).map_err(|e| e.to_string())?;
+        write_atomically(&self.path, true, |writer| writer.write_all(json.as_bytes()))
+            .map_err(|e| format!("failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+#[async_trait]
+impl TokenStorage for FileTokenStorage {
+    type LoadError = String;
+    type UpdateError = String;
+
+    async fn load_token(&mut self) -> Result<UserAccessToken, String> {
+        let json = fs::read_to_string(&self.path)
+            .map_err(|e| format!("failed to read {}: {}", self.path.display(), e))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("failed to parse {}: {}", self.path.display(), e))
+    }
+
+    async fn update_token(&mut self, token: &UserAccessToken) -> Result<(), String> {
+        info!("refreshed twitch token");
+        self.write(token)
+    }
+}
+
+/// Either a fixed token or one that is refreshed through Twitch's OAuth API.
+#[derive(Debug, Clone)]
+pub enum BotCredentials {
+    Static(StaticLoginCredentials),
+    Refreshing(RefreshingLoginCredentials<FileTokenStorage>),
+}
+
+impl BotCredentials {
+    pub fn from_config(twitch: &Credentials) -> Result<BotCredentials, String> {
+        if twitch.login.is_empty() {
+            return Err("no twitch login given".to_owned());
+        }
+        if let Some(token_file) = &twitch.token_file {
+            let (Some(client_id), Some(client_secret)) = (&twitch.client_id, &twitch.client_secret)
+            else {
+                return Err("token_file requires client_id and client_secret".to_owned());
+            };
+            let storage = FileTokenStorage::new(token_file.to_owned());
+            if let (Some(token), Some(refresh_token)) = (&twitch.token, &twitch.refresh_token) {
+                storage.seed(&strip_oauth(token), refresh_token)?;
+            }
+            return Ok(BotCredentials::Refreshing(
+                RefreshingLoginCredentials::init_with_username(
+                    Some(twitch.login.to_owned()),
+                    client_id.to_owned(),
+                    client_secret.to_owned(),
+                    storage,
+                ),
+            ));
+        }
+        match &twitch.token {
+            Some(token) => Ok(BotCredentials::Static(StaticLoginCredentials::new(
+                twitch.login.to_owned(),
+                Some(strip_oauth(token)),
+            ))),
+            None => Err("no twitch token given".to_owned()),
+        }
+    }
+}
+
+#[async_trait]
+impl LoginCredentials for BotCredentials {
+    type Error = String;
+
+    async fn get_credentials(&self) -> Result<CredentialsPair, String> {
+        match self {
+            BotCredentials::Static(credentials) => Ok(credentials.credentials.clone()),
+            BotCredentials::Refreshing(credentials) => credentials
+                .get_credentials()
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+fn strip_oauth(token: &str) -> String {
+    token.replacen("oauth:", "", 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::ScratchDir;
+
+    #[tokio::test]
+    async fn seeded_tokens_are_loaded_back() {
+        let dir = ScratchDir::new("token-seed");
+        let mut storage = FileTokenStorage::new(dir.join("token.json"));
+        storage.seed("access", "refresh").unwrap();
+        let token = storage.load_token().await.unwrap();
+        assert_eq!(token.access_token, "access");
+        assert_eq!(token.refresh_token, "refresh");
+        // Refreshed on the first connect
+        assert_eq!(token.created_at, DateTime::<Utc>::UNIX_EPOCH);
+        assert_eq!(token.expires_at, None);
+
+        // An existing token file is kept
+        storage.seed("other", "other").unwrap();
+        let token = storage.load_token().await.unwrap();
+        assert_eq!(token.access_token, "access");
+    }
+
+    #[tokio::test]
+    async fn refreshed_tokens_are_kept_private() {
+        let dir = ScratchDir::new("token-update");
+        let path = dir.join("token.json");
+        let mut storage = FileTokenStorage::new(path.to_owned());
+        let refreshed = UserAccessToken {
+            access_token: "new".to_owned(),
+            refresh_token: "newer".to_owned(),
+            created_at: Utc::now(),
+            expires_at: Some(Utc::now()),
+        };
+        storage.update_token(&refreshed).await.unwrap();
+        let token = storage.load_token().await.unwrap();
+        assert_eq!(token.access_token, refreshed.access_token);
+        assert_eq!(token.refresh_token, refreshed.refresh_token);
+        assert_eq!(token.created_at, refreshed.created_at);
+        assert_eq!(token.expires_at, refreshed.expires_at);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn broken_token_files_are_reported() {
+        let dir = ScratchDir::new("token-broken");
+        let path = dir.join("token.json");
+        let mut storage = FileTokenStorage::new(path.to_owned());
+        let error = storage.load_token().await.unwrap_err();
+        assert!(error.starts_with("failed to read "), "{}", error);
+        fs::write(&path, "{").unwrap();
+        let error = storage.load_token().await.unwrap_err();
+        assert!(error.starts_with("failed to parse "), "{}", error);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::compression;
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+/// Newest major version of the format that can be read.
+pub const MAX_MAJOR_VERSION: u32 = 1;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "PascalCase")]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Written since version 1.0, older logs have none.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct FileInfo {
+    pub version: Version,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+/// A Twitch id, a number in some places and versions and a string in
+/// others.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Id {
+    Number(u64),
+    Text(String),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Streamer {
+    #[serde(default)]
+    pub name: String,
+    pub id: Option<Id>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Video {
+    pub id: Option<Id>,
+    pub title: Option<String>,
+    pub created_at: Option<String>,
+    /// Seconds into the video the log starts and ends at
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub length: Option<f64>,
+    #[serde(rename = "viewCount")]
+    pub view_count: Option<u64>,
+    pub game: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Comment {
+    #[serde(rename = "_id")]
+    pub id: Option<String>,
+    pub created_at: Option<String>,
+    pub content_offset_seconds: Option<f64>,
+    pub commenter: Commenter,
+    pub message: Message,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Commenter {
+    #[serde(rename = "_id")]
+    pub id: Option<Id>,
+    /// The login
+    pub name: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Message {
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub bits_spent: u64,
+    #[serde(default)]
+    pub fragments: Vec<Fragment>,
+    #[serde(default)]
+    pub user_badges: Vec<Badge>,
+    pub user_color: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Fragment {
+    #[serde(default)]
+    pub text: String,
+    pub emoticon: Option<Emoticon>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Emoticon {
+    pub emoticon_id: Option<Id>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Badge {
+    #[serde(rename = "_id")]
+    pub id: Option<String>,
+    pub version: Option<String>,
+}
+
+impl Message {
+    /// The text of the message. Some old logs leave `body` empty and only
+    /// have the fragments.
+    pub fn text(&self) -> String {
+        if self.body.is_empty() {
+            self.fragments.iter().map(|f| f.text.as_str()).collect()
+        } else {
+            self.body.to_owned()
+        }
+    }
+}
+
+/// Everything in a chat log but the comments.
+#[derive(Default, Debug)]
+pub struct Header {
+    /// `None` for logs written before version 1.0
+    pub file_info: Option<FileInfo>,
+    pub streamer: Option<Streamer>,
+    pub video: Option<Video>,
+}
+
+impl Header {
+    pub fn version(&self) -> Option<Version> {
+        self.file_info.as_ref().map(|info| info.version)
+    }
+}
+
+/// Reads the TwitchDownloader chat log at `path`, which may be compressed,
+/// one comment at a time, calling `f` with each, so the file is never held
+/// in memory as a whole. The comments before an error have been passed to
+/// `f` when it fails.
+pub fn read_chat(path: &Path, mut f: impl FnMut(Comment)) -> Result<Header, String> {
+    let reader = compression::open(path).map_err(|e| e.to_string())?;
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let mut header = Header::default();
+    ChatLog {
+        header: &mut header,
+        f: &mut f,
+    }
+    .deserialize(&mut deserializer)
+    .and_then(|_| deserializer.end())
+    .map_err(|e| e.to_string())?;
+    Ok(header)
+}
+
+fn check_version<E: de::Error>(version: Version) -> Result<(), E> {
+    if version.major > MAX_MAJOR_VERSION {
+        return Err(E::custom(format!(
+            "unsupported TwitchDownloader format version {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+struct ChatLog<'a, F> {
+    header: &'a mut Header,
+    f: &'a mut F,
+}
+
+/// The `comments` array, read one comment at a time.
+struct Comments<'a, F>(&'a mut F);
+
+impl<'de, F: FnMut(Comment)> DeserializeSeed<'de> for ChatLog<'_, F> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(Comment)> Visitor<'de> for ChatLog<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a TwitchDownloader chat log")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut found = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "FileInfo" => {
+                    let file_info: FileInfo = map.next_value()?;
+                    check_version(file_info.version)?;
+                    self.header.file_info = Some(file_info);
+                }
+                "streamer" => self.header.streamer = map.next_value()?,
+                "video" => self.header.video = map.next_value()?,
+                "comments" => {
+                    map.next_value_seed(Comments(&mut *self.f))?;
+                    found = true;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if !found {
+            return Err(de::Error::missing_field("comments"));
+        }
+        Ok(())
+    }
+}
+
+impl<'de, F: FnMut(Comment)> DeserializeSeed<'de> for Comments<'_, F> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(Comment)> Visitor<'de> for Comments<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of comments")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(comment) = seq.next_element::<Comment>()? {
+            (self.0)(comment);
+        }
+        Ok(())
+    }
+}
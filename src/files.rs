@@ -0,0 +1,75 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// `path` with `.tmp` appended to its file name, so files that only differ
+/// in extension don't share one.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Writes `path` with `write`, into a temporary file next to it that is
+/// renamed over `path` once complete, so a crash or a failed write never
+/// leaves a truncated file behind. If `private`, only the owner can read
+/// the file.
+pub fn write_atomically<E: From<io::Error>>(
+    path: &Path,
+    private: bool,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), E>,
+) -> Result<(), E> {
+    let tmp = tmp_path(path);
+    // A leftover file would keep its old permissions
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut writer = BufWriter::new(options.open(&tmp)?);
+    write(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// A fresh directory for one test's files, removed when the test ends.
+#[cfg(any(test, feature = "testing"))]
+pub struct ScratchDir(PathBuf);
+
+#[cfg(any(test, feature = "testing"))]
+impl ScratchDir {
+    pub fn new(test: &str) -> ScratchDir {
+        let dir = std::env::temp_dir().join(format!("sglypa-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        ScratchDir(dir)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl std::ops::Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
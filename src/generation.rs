@@ -0,0 +1,562 @@
+use crate::models::ModelKind;
+
+use log::info;
+use markov_strings::{ErrorType, Markov, MarkovResult};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+pub const STATE_SIZE: usize = 2;
+
+/// Tuning knobs for generating messages out of a `Markov` model.
+///
+/// `markov_strings` only accepts a plain `fn` as a filter, so the filter
+/// can't capture settings; instead `generate` checks every candidate
+/// against the config with `accepts`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenerationConfig {
+    pub min_refs: usize,
+    pub min_len: usize,
+    pub max_len: usize,
+    pub min_score: u16,
+    pub max_tries: u16,
+    pub a_till_proc: usize,
+    pub use_filter: bool,
+    pub show_refs: bool,
+    pub filter: String,
+    /// Milliseconds a reply may take to generate before it's given up
+    pub timeout_ms: u64,
+    pub relax: RelaxConfig,
+}
+
+/// Fallbacks for models that can't satisfy the filter. When `MAX_TRIES` runs
+/// out, generation is retried with MIN_REFS, MIN_SCORE and MIN_LEN scaled by
+/// each factor in turn.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelaxConfig {
+    pub main: Vec<f32>,
+    /// Live and combined models
+    pub learned: Vec<f32>,
+    pub personal: Vec<f32>,
+}
+
+impl Default for RelaxConfig {
+    fn default() -> Self {
+        Self {
+            main: vec![0.5],
+            learned: vec![0.5, 0.0],
+            personal: vec![0.5, 0.0],
+        }
+    }
+}
+
+impl RelaxConfig {
+    pub fn levels(&self, kind: &ModelKind) -> &[f32] {
+        match kind {
+            ModelKind::Main | ModelKind::Corpus(_) => &self.main,
+            ModelKind::Live(_) | ModelKind::Combined => &self.learned,
+            ModelKind::Personal(_) => &self.personal,
+        }
+    }
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            min_refs: 3,
+            min_len: 120,
+            max_len: 460,
+            min_score: 15,
+            max_tries: 20000,
+            a_till_proc: 420,
+            use_filter: false,
+            show_refs: false,
+            filter: String::new(),
+            timeout_ms: 5000,
+            relax: RelaxConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingError {
+    UnknownKey(String),
+    MissingValue(String),
+    InvalidValue { key: String, value: String },
+    OutOfRange(String),
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingError::UnknownKey(key) => write!(f, "unknown setting {}", key),
+            SettingError::MissingValue(key) => write!(f, "missing value for {}", key),
+            SettingError::InvalidValue { key, value } => {
+                write!(f, "invalid value {} for {}", value, key)
+            }
+            SettingError::OutOfRange(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SettingError {}
+
+impl GenerationConfig {
+    pub fn validate(&self) -> Result<(), SettingError> {
+        if self.min_len < 20 || self.max_len > 460 || self.min_len > self.max_len {
+            return Err(SettingError::OutOfRange(
+                "MIN_LEN and MAX_LEN must satisfy 20 <= MIN_LEN <= MAX_LEN <= 460".to_owned(),
+            ));
+        }
+        if !(10..=65000).contains(&self.max_tries) {
+            return Err(SettingError::OutOfRange(
+                "MAX_TRIES must be between 10 and 65000".to_owned(),
+            ));
+        }
+        if self.a_till_proc == 0 {
+            return Err(SettingError::OutOfRange(
+                "PROC must be at least 1".to_owned(),
+            ));
+        }
+        let relax = &self.relax;
+        if relax
+            .main
+            .iter()
+            .chain(&relax.learned)
+            .chain(&relax.personal)
+            .any(|factor| !(0.0..=1.0).contains(factor))
+        {
+            return Err(SettingError::OutOfRange(
+                "relax factors must be between 0 and 1".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether this config and `other` only differ in settings that don't
+    /// affect generation, so replies generated for one suit the other.
+    pub fn generates_like(&self, other: &GenerationConfig) -> bool {
+        GenerationConfig {
+            a_till_proc: other.a_till_proc,
+            show_refs: other.show_refs,
+            ..self.clone()
+        } == *other
+    }
+
+    /// This config with the minimums scaled by `factor`.
+    pub fn relaxed(&self, factor: f32) -> GenerationConfig {
+        let scale = |min: f32| (min * factor).round();
+        GenerationConfig {
+            min_refs: scale(self.min_refs as f32) as usize,
+            min_len: scale(self.min_len as f32) as usize,
+            min_score: scale(self.min_score as f32) as u16,
+            ..self.clone()
+        }
+    }
+
+    /// Sets a single setting by its chat name. Nothing is changed if the
+    /// value doesn't parse or the resulting config is invalid.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingError> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, SettingError> {
+            value.parse().map_err(|_| SettingError::InvalidValue {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            })
+        }
+
+        let mut updated = self.clone();
+        match key.to_uppercase().as_ref() {
+            "MIN_REFS" => updated.min_refs = parse(key, value)?,
+            "MIN_LEN" => updated.min_len = parse(key, value)?,
+            "MAX_LEN" => updated.max_len = parse(key, value)?,
+            "MIN_SCORE" => updated.min_score = parse(key, value)?,
+            "MAX_TRIES" => updated.max_tries = parse(key, value)?,
+            "PROC" => updated.a_till_proc = parse(key, value)?,
+            "USE_FILTER" => updated.use_filter = parse(key, value)?,
+            "SHOW_REFS" => updated.show_refs = parse(key, value)?,
+            "FILTER" => updated.filter = value.to_owned(),
+            _ => return Err(SettingError::UnknownKey(key.to_owned())),
+        }
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+
+    /// The settings `set` knows, by their chat names.
+    pub fn show(&self) -> String {
+        format!(
+            "MIN_REFS={} MIN_LEN={} MAX_LEN={} MIN_SCORE={} MAX_TRIES={} PROC={} \
+             USE_FILTER={} SHOW_REFS={} FILTER={:?}",
+            self.min_refs,
+            self.min_len,
+            self.max_len,
+            self.min_score,
+            self.max_tries,
+            self.a_till_proc,
+            self.use_filter,
+            self.show_refs,
+            self.filter,
+        )
+    }
+
+    pub fn accepts(&self, r: &MarkovResult) -> bool {
+        self.rejections(r).next().is_none()
+    }
+
+    /// Every filter clause `r` fails.
+    pub fn rejections(&self, r: &MarkovResult) -> impl Iterator<Item = Rejection> {
+        [
+            // A minimal relative score and number of references
+            // The thresholds are relative to your input
+            (Rejection::Score, r.score < self.min_score),
+            (Rejection::Refs, r.refs.len() < self.min_refs),
+            // We want to generate random messages
+            (
+                Rejection::Length,
+                r.text.len() < self.min_len || r.text.len() > self.max_len,
+            ),
+            // No commands
+            (Rejection::Command, r.text.starts_with('!')),
+            // No mentions
+            // (Rejection::Mention, r.text.contains('@')),
+            (
+                Rejection::Filter,
+                self.use_filter && !r.text.contains(&self.filter),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(rejection, failed)| failed.then_some(rejection))
+    }
+}
+
+/// A clause of `GenerationConfig::accepts`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rejection {
+    Score,
+    Refs,
+    Length,
+    Command,
+    Filter,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rejection::Score => "MIN_SCORE",
+            Rejection::Refs => "MIN_REFS",
+            Rejection::Length => "MIN_LEN/MAX_LEN",
+            Rejection::Command => "! prefix",
+            Rejection::Filter => "FILTER",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What a batch of raw candidates looked like against the filter.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub attempts: usize,
+    pub accepted: usize,
+    /// How many candidates failed each clause
+    pub rejected: HashMap<Rejection, usize>,
+    /// Summed over accepted candidates
+    pub score_sum: u64,
+    pub refs_sum: usize,
+}
+
+impl Diagnostics {
+    /// Counts the clauses of `config` that `result` fails, or adds it to the
+    /// accepted ones. `attempts` is counted by the caller.
+    pub fn record(&mut self, config: &GenerationConfig, result: &MarkovResult) {
+        let mut accepted = true;
+        for rejection in config.rejections(result) {
+            accepted = false;
+            *self.rejected.entry(rejection).or_default() += 1;
+        }
+        if accepted {
+            self.accepted += 1;
+            self.score_sum += result.score as u64;
+            self.refs_sum += result.refs.len();
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} accepted ({:.1}%)",
+            self.accepted,
+            self.attempts,
+            100.0 * self.accepted as f64 / self.attempts.max(1) as f64
+        )?;
+        let mut rejected = self.rejected.iter().collect::<Vec<_>>();
+        rejected.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        if !rejected.is_empty() {
+            let rejected = rejected
+                .iter()
+                .map(|(rejection, count)| format!("{} {}", rejection, count))
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(f, ", rejected by {}", rejected)?;
+        }
+        if self.accepted > 0 {
+            write!(
+                f,
+                ", accepted avg score {:.1}, avg refs {:.1}",
+                self.score_sum as f64 / self.accepted as f64,
+                self.refs_sum as f64 / self.accepted as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates up to `attempts` raw candidates and checks each against
+/// `config`, stopping early at `deadline`.
+pub fn diagnose(
+    m: &Markov,
+    config: &GenerationConfig,
+    attempts: usize,
+    deadline: Instant,
+) -> Result<Diagnostics, ErrorType> {
+    let mut diagnostics = Diagnostics::default();
+    while diagnostics.attempts < attempts && Instant::now() < deadline {
+        diagnostics.attempts += 1;
+        match m.generate() {
+            Ok(result) => diagnostics.record(config, &result),
+            // No sentence came to an end, nothing to check
+            Err(ErrorType::TriesExceeded) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// Prepares a freshly created, still empty model.
+pub fn update_markov(m: &mut Markov, config: &GenerationConfig) {
+    m.set_state_size(STATE_SIZE).unwrap();
+    apply_settings(m, config);
+}
+
+/// Applies the settings that can still change once a model has a corpus.
+pub fn apply_settings(m: &mut Markov, config: &GenerationConfig) {
+    m.unset_filter().set_max_tries(config.max_tries);
+}
+
+/// Generates a message with the `kind` model, relaxing `config` step by
+/// step as configured in `config.relax` until something passes. Gives up
+/// after `deadline`.
+pub fn generate(
+    m: &Markov,
+    config: &GenerationConfig,
+    kind: &ModelKind,
+    deadline: Instant,
+) -> Result<MarkovResult, ErrorType> {
+    let (level, result) = generate_relaxed(m, config, kind, deadline)?;
+    if level > 0 {
+        let factor = config.relax.levels(kind)[level - 1];
+        info!(
+            "generated for {:?} at relaxation level {} (x{})",
+            kind, level, factor
+        );
+    }
+    Ok(result)
+}
+
+/// `generate`, also returning the relaxation level the message passed at:
+/// 0 for `config` itself, then 1 for the first factor and so on.
+pub fn generate_relaxed(
+    m: &Markov,
+    config: &GenerationConfig,
+    kind: &ModelKind,
+    deadline: Instant,
+) -> Result<(usize, MarkovResult), ErrorType> {
+    let levels = std::iter::once(1.0).chain(config.relax.levels(kind).iter().copied());
+    for (level, factor) in levels.enumerate() {
+        match generate_strict(m, &config.relaxed(factor), deadline) {
+            Ok(result) => return Ok((level, result)),
+            Err(ErrorType::TriesExceeded) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(ErrorType::TriesExceeded)
+}
+
+/// Generates a message accepted by `config`, spending at most
+/// `config.max_tries` tries in total and giving up after `deadline`.
+pub fn generate_strict(
+    m: &Markov,
+    config: &GenerationConfig,
+    deadline: Instant,
+) -> Result<MarkovResult, ErrorType> {
+    let mut tries_left = config.max_tries;
+    while tries_left > 0 && Instant::now() < deadline {
+        let result = m.generate()?;
+        tries_left = tries_left.saturating_sub(result.tries);
+        if config.accepts(&result) {
+            return Ok(result);
+        }
+    }
+    Err(ErrorType::TriesExceeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use markov_strings::InputData;
+    use std::time::Duration;
+
+    fn result(text: &str, score: u16, refs: usize) -> MarkovResult {
+        MarkovResult {
+            text: text.to_owned(),
+            score,
+            refs: (0..refs).collect(),
+            tries: 1,
+        }
+    }
+
+    /// A model of a few short messages, so no message can reach 1000 refs.
+    fn markov(config: &GenerationConfig) -> Markov {
+        let mut markov = Markov::new();
+        update_markov(&mut markov, config);
+        let texts = [
+            "hello chat how are you",
+            "hello there chat",
+            "how are you all",
+        ];
+        markov.add_to_corpus(
+            texts
+                .iter()
+                .map(|text| InputData {
+                    text: text.to_string(),
+                    meta: None,
+                })
+                .collect(),
+        );
+        markov
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    #[test]
+    fn relaxed_scales_only_the_minimums() {
+        let config = GenerationConfig {
+            min_refs: 3,
+            min_len: 120,
+            min_score: 15,
+            max_len: 460,
+            ..GenerationConfig::default()
+        };
+        let relaxed = config.relaxed(0.5);
+        assert_eq!(relaxed.min_refs, 2);
+        assert_eq!(relaxed.min_len, 60);
+        assert_eq!(relaxed.min_score, 8);
+        assert_eq!(relaxed.max_len, 460);
+        assert_eq!(relaxed.max_tries, config.max_tries);
+        let relaxed = config.relaxed(0.0);
+        assert_eq!(
+            (relaxed.min_refs, relaxed.min_len, relaxed.min_score),
+            (0, 0, 0)
+        );
+        assert_eq!(config.relaxed(1.0), config);
+    }
+
+    #[test]
+    fn relaxation_levels_are_tried_in_order() {
+        let config = GenerationConfig {
+            min_refs: 1000,
+            min_len: 0,
+            min_score: 0,
+            max_tries: 10,
+            relax: RelaxConfig {
+                main: vec![0.5, 0.0, 0.5],
+                learned: vec![0.0],
+                personal: vec![],
+            },
+            ..GenerationConfig::default()
+        };
+        let markov = markov(&config);
+        let (level, result) =
+            generate_relaxed(&markov, &config, &ModelKind::Main, deadline()).unwrap();
+        assert_eq!(level, 2);
+        assert!(!result.text.is_empty());
+
+        // Each kind of model has its own levels
+        let kind = ModelKind::Live("chan".to_owned());
+        let (level, _) = generate_relaxed(&markov, &config, &kind, deadline()).unwrap();
+        assert_eq!(level, 1);
+        let kind = ModelKind::Personal("alice".to_owned());
+        let error = generate_relaxed(&markov, &config, &kind, deadline()).unwrap_err();
+        assert!(matches!(error, ErrorType::TriesExceeded));
+    }
+
+    #[test]
+    fn unrelaxed_messages_report_level_zero() {
+        let config = GenerationConfig {
+            min_refs: 0,
+            min_len: 0,
+            min_score: 0,
+            ..GenerationConfig::default()
+        };
+        let markov = markov(&config);
+        let (level, _) = generate_relaxed(&markov, &config, &ModelKind::Main, deadline()).unwrap();
+        assert_eq!(level, 0);
+    }
+
+    #[test]
+    fn diagnostics_count_every_failed_clause() {
+        let config = GenerationConfig {
+            min_refs: 2,
+            min_len: 5,
+            max_len: 20,
+            min_score: 10,
+            use_filter: true,
+            filter: "chat".to_owned(),
+            ..GenerationConfig::default()
+        };
+        let mut diagnostics = Diagnostics::default();
+        // Fails everything but the length
+        diagnostics.record(&config, &result("!hello there", 1, 1));
+        // Fails only the length
+        diagnostics.record(&config, &result("chat", 10, 2));
+        // Accepted
+        diagnostics.record(&config, &result("hello chat", 20, 3));
+        assert_eq!(diagnostics.accepted, 1);
+        let rejected = |rejection| diagnostics.rejected.get(&rejection).copied();
+        assert_eq!(rejected(Rejection::Score), Some(1));
+        assert_eq!(rejected(Rejection::Refs), Some(1));
+        assert_eq!(rejected(Rejection::Command), Some(1));
+        assert_eq!(rejected(Rejection::Filter), Some(1));
+        assert_eq!(rejected(Rejection::Length), Some(1));
+    }
+
+    #[test]
+    fn diagnostics_report_the_rate_and_averages() {
+        let config = GenerationConfig {
+            min_refs: 0,
+            min_len: 0,
+            min_score: 0,
+            ..GenerationConfig::default()
+        };
+        let mut diagnostics = Diagnostics {
+            attempts: 8,
+            ..Diagnostics::default()
+        };
+        diagnostics.record(&config, &result("hello", 10, 1));
+        diagnostics.record(&config, &result("hello", 15, 4));
+        diagnostics.record(&config, &result("!hello", 99, 9));
+        assert_eq!(
+            diagnostics.to_string(),
+            "2/8 accepted (25.0%), rejected by ! prefix 1, accepted avg score 12.5, avg refs 2.5"
+        );
+
+        let nothing = Diagnostics::default();
+        assert_eq!(nothing.to_string(), "0/0 accepted (0.0%)");
+    }
+}
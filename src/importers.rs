@@ -0,0 +1,387 @@
+use crate::compression::{self, Compression};
+use crate::downloader::read_chat;
+
+use glob::Pattern;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use twitch_irc::message::{IRCMessage, IRCPrefix};
+
+/// Name of the optional file in a streamer folder that says which format
+/// its chat logs are in.
+pub const MANIFEST: &str = "manifest.toml";
+
+/// Reads the chat logs of one archive format.
+pub trait Importer: Sync {
+    /// Reads the log at `path`, which may be compressed, calling `f` with
+    /// the author and text of every message. Returns how to describe the
+    /// log in the progress output. The messages before an error have been
+    /// passed to `f` when it fails.
+    fn import(&self, path: &Path, f: &mut dyn FnMut(String, String)) -> Result<String, String>;
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// TwitchDownloader `.json`
+    TwitchDownloader,
+    /// Chatterino `.log` or `.txt`, `[12:34:56]  login: text` per line
+    Chatterino,
+    /// Raw Twitch IRC `.irc`, a `PRIVMSG` with tags per line
+    Irc,
+    /// yt-dlp `.live_chat.json` of a YouTube stream
+    YtDlp,
+    /// `.csv` with a header naming the author and text columns
+    Csv,
+}
+
+impl Format {
+    /// The format of `path` judging by its extension, ignoring a compression
+    /// extension after it.
+    pub fn of(path: &Path) -> Option<Format> {
+        let name = log_name(path)?.to_lowercase();
+        if name.ends_with(".live_chat.json") {
+            return Some(Format::YtDlp);
+        }
+        match name.rsplit_once('.')?.1 {
+            "json" => Some(Format::TwitchDownloader),
+            "log" | "txt" => Some(Format::Chatterino),
+            "irc" => Some(Format::Irc),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn importer(self) -> &'static dyn Importer {
+        match self {
+            Format::TwitchDownloader => &TwitchDownloader,
+            Format::Chatterino => &Chatterino,
+            Format::Irc => &Irc,
+            Format::YtDlp => &YtDlp,
+            Format::Csv => &Csv,
+        }
+    }
+}
+
+/// The file name of the log at `path` without a compression extension.
+fn log_name(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    match Compression::of(path) {
+        Some(compression) => name
+            .strip_suffix(compression.extension())?
+            .strip_suffix('.'),
+        None => Some(name),
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ManifestFile {
+    format: Option<Format>,
+    files: BTreeMap<String, Format>,
+}
+
+/// Which format the chat logs in a streamer folder are in, read from its
+/// `manifest.toml`:
+///
+/// ```toml
+/// # Every log in the folder not matched below
+/// format = "chatterino"
+///
+/// # By file name, the first matching pattern in alphabetical order wins
+/// [files]
+/// "*.log" = "irc"
+/// ```
+///
+/// Logs the manifest says nothing about go by their extension.
+#[derive(Default)]
+pub struct Manifest {
+    format: Option<Format>,
+    files: Vec<(Pattern, Format)>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the streamer folder `dir`, which has none if
+    /// the file doesn't exist.
+    pub fn load(dir: &Path) -> Result<Manifest, String> {
+        let path = dir.join(MANIFEST);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Manifest::default()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let file: ManifestFile = toml::from_str(&text).map_err(|e| e.to_string())?;
+        let mut files = Vec::new();
+        for (pattern, format) in file.files {
+            let pattern = Pattern::new(&pattern).map_err(|e| format!("{}: {}", pattern, e))?;
+            files.push((pattern, format));
+        }
+        Ok(Manifest {
+            format: file.format,
+            files,
+        })
+    }
+
+    /// The format of the log at `path`, `None` if it's unknown. Patterns
+    /// match the name without a compression extension, so `"*.log"` covers
+    /// `chat.log.gz` too.
+    pub fn format(&self, path: &Path) -> Option<Format> {
+        let name = log_name(path)?;
+        self.files
+            .iter()
+            .find(|(pattern, _)| pattern.matches(name))
+            .map(|(_, format)| *format)
+            .or(self.format)
+            .or_else(|| Format::of(path))
+    }
+}
+
+struct TwitchDownloader;
+
+impl Importer for TwitchDownloader {
+    fn import(&self, path: &Path, f: &mut dyn FnMut(String, String)) -> Result<String, String> {
+        let header = read_chat(path, |comment| {
+            f(comment.commenter.name, comment.message.text());
+        })?;
+        Ok(match header.version() {
+            Some(version) => format!("v{}", version),
+            None => "legacy".to_owned(),
+        })
+    }
+}
+
+/// Calls `f` with every line of the log at `path` and its number, counting
+/// from 1.
+fn read_lines(
+    path: &Path,
+    mut f: impl FnMut(usize, &str) -> Result<(), String>,
+) -> Result<(), String> {
+    let reader = BufReader::new(compression::open(path).map_err(|e| e.to_string())?);
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("line {}: {}", index + 1, e))?;
+        f(index + 1, line.trim_end_matches('\r'))?;
+    }
+    Ok(())
+}
+
+struct Chatterino;
+
+impl Importer for Chatterino {
+    fn import(&self, path: &Path, f: &mut dyn FnMut(String, String)) -> Result<String, String> {
+        read_lines(path, |_, line| {
+            if let Some((author, text)) = chatterino_message(line) {
+                f(author.to_owned(), text.to_owned());
+            }
+            Ok(())
+        })?;
+        Ok("chatterino".to_owned())
+    }
+}
+
+/// The author and text of a chat message line, `None` for the
+/// `# Start logging` headers and lines about timeouts, subs and so on.
+/// Users with a localized display name are logged as `Name (login)`.
+fn chatterino_message(line: &str) -> Option<(&str, &str)> {
+    let (_, line) = line.strip_prefix('[')?.split_once(']')?;
+    let (author, text) = line.trim_start().split_once(": ")?;
+    let author = match author.split_once(" (") {
+        Some((_, login)) => login.strip_suffix(')')?,
+        None => author,
+    };
+    if author.is_empty() || author.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((author, text))
+}
+
+struct Irc;
+
+impl Importer for Irc {
+    fn import(&self, path: &Path, f: &mut dyn FnMut(String, String)) -> Result<String, String> {
+        read_lines(path, |number, line| {
+            // Some loggers put a `[timestamp]` before every line
+            let line = match line.strip_prefix('[').and_then(|l| l.split_once("] ")) {
+                Some((_, rest)) => rest,
+                None => line,
+            };
+            if line.trim().is_empty() {
+                return Ok(());
+            }
+            let message = IRCMessage::parse(line).map_err(|e| format!("line {}: {}", number, e))?;
+            if message.command != "PRIVMSG" {
+                return Ok(());
+            }
+            let Some(IRCPrefix::Full { nick, .. }) = message.prefix else {
+                return Err(format!("line {}: PRIVMSG without a sender", number));
+            };
+            let Some(text) = message.params.get(1) else {
+                return Err(format!("line {}: PRIVMSG without a text", number));
+            };
+            let text = text
+                .strip_prefix("\u{1}ACTION ")
+                .map_or(text.as_str(), |action| action.trim_end_matches('\u{1}'));
+            f(nick, text.to_owned());
+            Ok(())
+        })?;
+        Ok("irc".to_owned())
+    }
+}
+
+/// One line of a yt-dlp `live_chat.json`, only the parts with messages.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveChatLine {
+    replay_chat_item_action: Option<ReplayChatItemAction>,
+}
+
+#[derive(Deserialize)]
+struct ReplayChatItemAction {
+    #[serde(default)]
+    actions: Vec<LiveChatAction>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveChatAction {
+    add_chat_item_action: Option<AddChatItemAction>,
+}
+
+#[derive(Deserialize)]
+struct AddChatItemAction {
+    item: LiveChatItem,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveChatItem {
+    live_chat_text_message_renderer: Option<LiveChatMessage>,
+    /// Super Chats, which may come with a message
+    live_chat_paid_message_renderer: Option<LiveChatMessage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveChatMessage {
+    author_name: Option<SimpleText>,
+    message: Option<Runs>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SimpleText {
+    simple_text: String,
+}
+
+#[derive(Deserialize)]
+struct Runs {
+    runs: Vec<Run>,
+}
+
+#[derive(Deserialize)]
+struct Run {
+    text: Option<String>,
+    emoji: Option<Emoji>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Emoji {
+    /// The emoji itself for standard ones
+    emoji_id: String,
+    #[serde(default)]
+    shortcuts: Vec<String>,
+    #[serde(default)]
+    is_custom_emoji: bool,
+}
+
+impl Runs {
+    /// The text with custom emojis as their `:shortcut:`.
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for run in self.runs.iter() {
+            match (&run.text, &run.emoji) {
+                (Some(run), _) => text.push_str(run),
+                (None, Some(emoji)) if emoji.is_custom_emoji => {
+                    text.push_str(emoji.shortcuts.first().unwrap_or(&emoji.emoji_id))
+                }
+                (None, Some(emoji)) => text.push_str(&emoji.emoji_id),
+                (None, None) => {}
+            }
+        }
+        text
+    }
+}
+
+struct YtDlp;
+
+impl Importer for YtDlp {
+    fn import(&self, path: &Path, f: &mut dyn FnMut(String, String)) -> Result<String, String> {
+        read_lines(path, |number, line| {
+            if line.trim().is_empty() {
+                return Ok(());
+            }
+            let line: LiveChatLine =
+                serde_json::from_str(line).map_err(|e| format!("line {}: {}", number, e))?;
+            let actions = line.replay_chat_item_action.map(|replay| replay.actions);
+            for action in actions.into_iter().flatten() {
+                let Some(AddChatItemAction { item }) = action.add_chat_item_action else {
+                    continue;
+                };
+                let message = item
+                    .live_chat_text_message_renderer
+                    .or(item.live_chat_paid_message_renderer);
+                if let Some(LiveChatMessage {
+                    author_name: Some(author),
+                    message: Some(runs),
+                }) = message
+                {
+                    // Handles are shown as `@handle`
+                    let author = author.simple_text.trim_start_matches('@').to_owned();
+                    f(author, runs.text());
+                }
+            }
+            Ok(())
+        })?;
+        Ok("yt-dlp".to_owned())
+    }
+}
+
+/// Header names of the author and text columns of a CSV export, checked in
+/// this order and ignoring case.
+const CSV_AUTHOR_COLUMNS: &[&str] = &[
+    "author", "login", "username", "user", "name", "nick", "sender",
+];
+const CSV_TEXT_COLUMNS: &[&str] = &["message", "text", "body", "content", "msg"];
+
+struct Csv;
+
+impl Importer for Csv {
+    fn import(&self, path: &Path, f: &mut dyn FnMut(String, String)) -> Result<String, String> {
+        let reader = compression::open(path).map_err(|e| e.to_string())?;
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+        let column = |names: &[&str]| {
+            names.iter().find_map(|name| {
+                headers
+                    .iter()
+                    .position(|header| header.trim().eq_ignore_ascii_case(name))
+            })
+        };
+        let (Some(author), Some(text)) = (column(CSV_AUTHOR_COLUMNS), column(CSV_TEXT_COLUMNS))
+        else {
+            return Err(format!(
+                "no author and text columns in {}",
+                headers.iter().collect::<Vec<_>>().join(",")
+            ));
+        };
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            if let (Some(author), Some(text)) = (record.get(author), record.get(text)) {
+                f(author.to_owned(), text.to_owned());
+            }
+        }
+        Ok("csv".to_owned())
+    }
+}
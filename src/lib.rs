@@ -0,0 +1,955 @@
+pub mod chat;
+pub mod cli;
+pub mod commands;
+pub mod compression;
+pub mod config;
+pub mod console;
+pub mod cooldown;
+pub mod credentials;
+pub mod downloader;
+pub mod files;
+pub mod generation;
+pub mod importers;
+pub mod models;
+pub mod outbound;
+pub mod pool;
+pub mod state;
+pub mod vods;
+
+use chat::{ChatSink, ChatSource};
+use commands::{Action, CommandArgs, Role};
+use config::{validate_profiles, Args, ChannelProfile, Config};
+use cooldown::{Cooldowns, Verdict};
+use credentials::BotCredentials;
+use generation::{diagnose, generate, update_markov, GenerationConfig, SettingError, STATE_SIZE};
+use markov_strings::*;
+use models::{corpus_name, ModelKind, Models};
+use outbound::{Outbound, OutboundConfig};
+use pool::ReplyPool;
+use state::State;
+use twitch_irc::message::{PrivmsgMessage, ServerMessage};
+use twitch_irc::{ClientConfig, SecureTCPTransport, TwitchIRCClient};
+
+use log::{error, info};
+use rand::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+/// Candidates generated by `!diag`.
+const DIAG_ATTEMPTS: usize = 200;
+
+/// How often changes held back by running generations are retried.
+const PENDING_RETRY: Duration = Duration::from_millis(100);
+
+/// A change to the models made while chatting, which waits for the
+/// generations holding them instead of blocking the receive loop.
+enum ModelChange {
+    /// A chat message to learn in a channel
+    Learn(String, InputData),
+    /// See `reset_learning`
+    Reset(Option<String>),
+    /// Apply the current settings, see `set_generation`
+    Settings,
+}
+
+pub struct Sglypa {
+    owners: HashSet<String>,
+    moderators: HashSet<String>,
+    channels: HashSet<String>,
+    /// The moderators and channels from the config file, which changes to the
+    /// above are remembered against
+    default_moderators: HashSet<String>,
+    default_channels: HashSet<String>,
+    /// Where changes to the above are remembered, `None` in the console
+    state_file: Option<PathBuf>,
+    sink: Arc<dyn ChatSink>,
+    /// Taken by `run`
+    source: Option<Box<dyn ChatSource>>,
+    outbound: Outbound,
+    /// Whether the bot answers everyone, by channel
+    replying: HashMap<String, bool>,
+    default_replying: bool,
+    profiles: HashMap<String, ChannelProfile>,
+    cooldowns: Cooldowns,
+    generation: GenerationConfig,
+    /// The settings from the config file, for `!settings reset`
+    default_generation: GenerationConfig,
+    /// Shared with the workers generating replies, which hold a read lock
+    /// for as long as they generate
+    models: Arc<RwLock<Models>>,
+    /// Changes not made to the models yet because they were busy, in order
+    pending_changes: Vec<ModelChange>,
+    /// Name of the corpus each mapped channel speaks like
+    corpora: HashMap<String, String>,
+    /// Streamer folders of each corpus
+    corpus_streamers: BTreeMap<String, Vec<String>>,
+    combined_live: bool,
+    /// See `Config::strict_vods`
+    strict_vods: bool,
+    pool: ReplyPool,
+    snapshot: PathBuf,
+    /// Whether the snapshot is only loaded and never saved, as in the console
+    read_only_snapshot: bool,
+    autosave: Option<Duration>,
+    /// Whether the models changed since the last snapshot, also set by a
+    /// failed save on a worker
+    dirty: Arc<AtomicBool>,
+    /// Whether a snapshot is being saved on a worker
+    saving: Arc<AtomicBool>,
+}
+
+impl Sglypa {
+    /// Creates the bot chatting on Twitch.
+    pub fn twitch(config: &Config, credentials: BotCredentials) -> Result<Sglypa, String> {
+        let client_config = ClientConfig::new_simple(credentials);
+        let (incoming_messages, client) =
+            TwitchIRCClient::<SecureTCPTransport, BotCredentials>::new(client_config);
+        Self::new(
+            config,
+            Arc::new(client),
+            Box::new(incoming_messages),
+            config.outbound.clone(),
+        )
+    }
+
+    /// Creates the bot with the owners, moderators and channels from
+    /// `config` and the changes to them remembered in the state file. Only
+    /// the config decides who is an owner. Messages are
+    /// handled as they come from `source` once `run` is called, and sent to
+    /// `sink` within the limits of `outbound`.
+    pub fn new(
+        config: &Config,
+        sink: Arc<dyn ChatSink>,
+        source: Box<dyn ChatSource>,
+        outbound: OutboundConfig,
+    ) -> Result<Sglypa, String> {
+        let state = State::load(&config.state_file)?;
+        let twitch_name = &config.twitch.login;
+        let mut owners = HashSet::<String>::new();
+        if !twitch_name.is_empty() {
+            owners.insert(twitch_name.to_owned());
+        }
+        owners.extend(config.owners.iter().map(|s| s.to_lowercase()));
+        let mut default_moderators = owners.clone();
+        default_moderators.extend(config.moderators.iter().map(|s| s.to_lowercase()));
+        let mut moderators = default_moderators.clone();
+        moderators.extend(state.moderators);
+        moderators.retain(|name| !state.removed_moderators.contains(name));
+        let default_channels = config
+            .channels
+            .iter()
+            .map(|s| s.to_lowercase())
+            .collect::<HashSet<_>>();
+        let mut channels = default_channels.clone();
+        channels.extend(state.channels);
+        channels.retain(|channel| !state.left_channels.contains(channel));
+        let models = Arc::new(RwLock::new(Models::default()));
+        let pool = ReplyPool::spawn(
+            models.clone(),
+            config.pool.clone(),
+            config.generation.clone(),
+        );
+        Ok(Self {
+            owners,
+            moderators,
+            channels,
+            default_moderators,
+            default_channels,
+            state_file: Some(config.state_file.to_owned()),
+            outbound: Outbound::spawn(sink.clone(), outbound),
+            sink,
+            source: Some(source),
+            replying: config
+                .profiles
+                .iter()
+                .filter_map(|(channel, profile)| Some((channel.to_owned(), profile.replying?)))
+                .collect(),
+            default_replying: config.replying,
+            profiles: config.profiles.clone(),
+            cooldowns: Cooldowns::new(config.cooldowns.clone()),
+            generation: config.generation.clone(),
+            default_generation: config.generation.clone(),
+            models,
+            pending_changes: Vec::new(),
+            corpora: config
+                .corpora
+                .iter()
+                .map(|(channel, streamers)| (channel.to_owned(), corpus_name(streamers)))
+                .collect(),
+            corpus_streamers: config.corpus_streamers(),
+            combined_live: config.combined_live,
+            strict_vods: config.strict_vods,
+            pool,
+            snapshot: config.snapshot.to_owned(),
+            read_only_snapshot: false,
+            autosave: (config.autosave_secs > 0).then(|| Duration::from_secs(config.autosave_secs)),
+            dirty: Arc::new(AtomicBool::new(false)),
+            saving: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Remembers how the moderators and channels differ from the config.
+    pub fn save_state(&self) {
+        let Some(state_file) = &self.state_file else {
+            return;
+        };
+        let difference =
+            |a: &HashSet<String>, b: &HashSet<String>| a.difference(b).cloned().collect();
+        let state = State {
+            moderators: difference(&self.moderators, &self.default_moderators),
+            removed_moderators: difference(&self.default_moderators, &self.moderators),
+            channels: difference(&self.channels, &self.default_channels),
+            left_channels: difference(&self.default_channels, &self.channels),
+        };
+        if let Err(e) = state.save(state_file) {
+            error!("{}", e);
+        }
+    }
+
+    /// Trains the main (and optionally personal) models, see
+    /// `Models::train_main`.
+    pub fn train_from_vods(
+        &mut self,
+        vods_dir: &Path,
+        streamers: &[String],
+        personal: bool,
+        message_filter: Option<fn(String, String) -> bool>,
+    ) -> Result<(), String> {
+        self.models_mut().train_main(
+            vods_dir,
+            streamers,
+            personal,
+            message_filter,
+            self.strict_vods,
+            &self.generation,
+        )?;
+        self.pool.invalidate(None);
+        Ok(())
+    }
+
+    /// Trains the models of the corpora in `corpus_streamers` that aren't
+    /// built yet, returning whether there were any.
+    pub fn train_corpora(
+        &mut self,
+        vods_dir: &Path,
+        message_filter: Option<fn(String, String) -> bool>,
+    ) -> Result<bool, String> {
+        let missing = self
+            .corpus_streamers
+            .iter()
+            .filter(|(corpus, _)| !self.models().corpus_markov.contains_key(*corpus))
+            .collect::<Vec<_>>();
+        for (corpus, streamers) in missing.iter() {
+            self.models_mut().train_corpus(
+                corpus,
+                vods_dir,
+                streamers,
+                message_filter,
+                self.strict_vods,
+                &self.generation,
+            )?;
+            self.pool
+                .invalidate(Some(&ModelKind::Corpus(corpus.to_string())));
+        }
+        Ok(!missing.is_empty())
+    }
+
+    pub fn models(&self) -> RwLockReadGuard<'_, Models> {
+        self.models.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits for running generations to finish, which may take up to
+    /// `timeout_ms`, so it's only for before `run`. Changes while chatting go
+    /// through `apply_pending`.
+    pub fn models_mut(&self) -> RwLockWriteGuard<'_, Models> {
+        self.models.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn join(&mut self, channel: &str) {
+        let channel = channel.to_lowercase();
+        if self.sink.join(&channel).is_ok() {
+            info!("joined {}", channel);
+            if self.channels.insert(channel) {
+                self.save_state();
+            }
+        } else {
+            info!("failed to join {}", channel);
+        }
+    }
+
+    pub fn leave(&mut self, channel: &str) {
+        let channel = channel.to_lowercase();
+        self.sink.part(&channel);
+        info!("left {}", channel);
+        if self.channels.remove(&channel) {
+            self.save_state();
+        }
+    }
+
+    /// Joins every channel from the config and the state file.
+    pub fn join_remembered(&mut self) {
+        for channel in self.channels.clone() {
+            self.join(&channel);
+        }
+    }
+
+    /// Saves the snapshot and waits for it, so it's only for before `run`,
+    /// see `spawn_save`.
+    pub fn save_snapshot(&mut self) -> Result<(), String> {
+        if self.read_only_snapshot {
+            info!("not saving the read-only snapshot");
+            return Ok(());
+        }
+        let start = Instant::now();
+        self.apply_pending();
+        self.models().save(&self.snapshot)?;
+        // Whatever is still pending goes into the next snapshot
+        self.dirty
+            .store(!self.pending_changes.is_empty(), Ordering::Relaxed);
+        info!(
+            "saved snapshot {} in {:?}",
+            self.snapshot.display(),
+            start.elapsed()
+        );
+        Ok(())
+    }
+
+    /// Saves the snapshot on a blocking worker so chat keeps being handled
+    /// meanwhile, replying to `msg` when it's done if given. The worker
+    /// holds a read lock, so changes to the models wait in `pending_changes`.
+    pub fn spawn_save(&mut self, msg: Option<&PrivmsgMessage>) {
+        if self.read_only_snapshot {
+            if let Some(msg) = msg {
+                self.outbound
+                    .reply(msg, "The snapshot is read-only".to_owned());
+            }
+            return;
+        }
+        if self.saving.swap(true, Ordering::AcqRel) {
+            if let Some(msg) = msg {
+                self.outbound
+                    .reply(msg, "Already saving snapshot".to_owned());
+            }
+            return;
+        }
+        self.apply_pending();
+        // Whatever is still pending goes into the next snapshot
+        self.dirty
+            .store(!self.pending_changes.is_empty(), Ordering::Relaxed);
+        let models = self.models.clone();
+        let snapshot = self.snapshot.clone();
+        let dirty = self.dirty.clone();
+        let saving = self.saving.clone();
+        let outbound = self.outbound.clone();
+        let msg = msg.cloned();
+        tokio::task::spawn_blocking(move || {
+            let start = Instant::now();
+            let result = models
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .save(&snapshot);
+            saving.store(false, Ordering::Release);
+            let reply = match result {
+                Ok(()) => {
+                    info!(
+                        "saved snapshot {} in {:?}",
+                        snapshot.display(),
+                        start.elapsed()
+                    );
+                    "Saved snapshot"
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    dirty.store(true, Ordering::Relaxed);
+                    "Failed to save snapshot"
+                }
+            };
+            if let Some(msg) = msg {
+                outbound.reply(&msg, reply.to_owned());
+            }
+        });
+    }
+
+    pub fn load_snapshot(&mut self) -> Result<(), String> {
+        let start = Instant::now();
+        *self.models_mut() = Models::load(&self.snapshot, &self.generation)?;
+        self.pool.invalidate(None);
+        self.dirty.store(false, Ordering::Relaxed);
+        info!(
+            "loaded snapshot {} in {:?}",
+            self.snapshot.display(),
+            start.elapsed()
+        );
+        Ok(())
+    }
+
+    pub async fn run(&mut self) {
+        let Some(mut source) = self.source.take() else {
+            return;
+        };
+        // Without autosave the interval is never polled, but it can't be
+        // `Duration::MAX` either as that overflows `Instant`
+        let mut autosave =
+            tokio::time::interval(self.autosave.unwrap_or(Duration::from_secs(86400)));
+        autosave.tick().await;
+        let mut retry = tokio::time::interval(PENDING_RETRY);
+        loop {
+            let message = tokio::select! {
+                _ = retry.tick(), if !self.pending_changes.is_empty() => {
+                    self.apply_pending();
+                    continue;
+                }
+                message = source.recv() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                _ = autosave.tick(), if self.autosave.is_some() => {
+                    if self.dirty.load(Ordering::Relaxed) {
+                        self.spawn_save(None);
+                    }
+                    continue;
+                }
+            };
+            match message {
+                ServerMessage::Privmsg(msg) => {
+                    self.handle_msg(&msg);
+                    info!(
+                        "(#{}) {}: {}",
+                        msg.channel_login, msg.sender.name, msg.message_text
+                    );
+                }
+                ServerMessage::Whisper(msg) => {
+                    info!("(w) {}: {}", msg.sender.name, msg.message_text);
+                }
+                ServerMessage::Notice(msg) => {
+                    info!("notice: {}", msg.message_text);
+                    if let (Some(channel), Some(message_id)) = (&msg.channel_login, &msg.message_id)
+                    {
+                        self.outbound.notice(channel, message_id);
+                    }
+                }
+                ServerMessage::UserState(msg) => {
+                    let privileged = msg.badges.iter().any(|badge| {
+                        matches!(badge.name.as_str(), "broadcaster" | "moderator" | "vip")
+                    });
+                    self.outbound.set_privileged(&msg.channel_login, privileged);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Stops the bot once `run` returned, waiting for the replies still being
+    /// generated and for everything queued to be sent.
+    pub async fn finish(self) {
+        let outbound = self.outbound.clone();
+        drop(self);
+        outbound.finish().await;
+    }
+
+    /// Says `message` in `channel` as a stair `length` steps high, all of it
+    /// or, if it wouldn't fit in the outbound queue, none of it.
+    pub fn say_stair(&mut self, channel: &str, length: usize, message: &str) -> Result<(), String> {
+        let max_length = self.outbound.max_queue().saturating_add(1) / 2;
+        if length > max_length {
+            return Err(format!("Stairs can be at most {} steps high", max_length));
+        }
+        let steps = (1..length)
+            .chain((1..=length).rev())
+            .map(|i| (message.to_owned() + " ").repeat(i).trim_end().to_owned())
+            .collect();
+        self.outbound.say_all(channel, steps);
+        Ok(())
+    }
+
+    pub fn is_super_privileged(&self, msg: &PrivmsgMessage) -> bool {
+        self.owners.contains(&msg.sender.login)
+    }
+
+    pub fn is_privileged(&self, msg: &PrivmsgMessage) -> bool {
+        self.moderators.contains(&msg.sender.login) || self.owners.contains(&msg.sender.login)
+    }
+
+    /// The model `!sglypa` uses in `channel`.
+    pub fn main_kind(&self, channel: &str) -> ModelKind {
+        match self.corpora.get(channel) {
+            Some(corpus) => ModelKind::Corpus(corpus.to_owned()),
+            None => ModelKind::Main,
+        }
+    }
+
+    pub fn is_replying(&self, channel: &str) -> bool {
+        self.replying
+            .get(channel)
+            .copied()
+            .unwrap_or(self.default_replying)
+    }
+
+    /// The settings in `channel`, the global ones with the channel's
+    /// profile applied.
+    pub fn generation_for(&self, channel: &str) -> GenerationConfig {
+        match self.profiles.get(channel) {
+            Some(profile) => profile.apply(&self.generation),
+            None => self.generation.clone(),
+        }
+    }
+
+    pub fn has_role(&self, msg: &PrivmsgMessage, role: Role) -> bool {
+        match role {
+            Role::Everyone => self.is_replying(&msg.channel_login) || self.is_privileged(msg),
+            Role::Moderator => self.is_privileged(msg),
+            Role::Owner => self.is_super_privileged(msg),
+        }
+    }
+
+    pub fn handle_msg(&mut self, msg: &PrivmsgMessage) {
+        self.handle_learn(msg);
+        let tokens = commands::tokenize(&msg.message_text);
+        if let Some((name, words)) = &tokens {
+            if let Some(command) = commands::find(name) {
+                if self.has_role(msg, command.role) {
+                    match command.parse_args(words) {
+                        Ok(args) => {
+                            if self.check_cooldown(msg, command.name) {
+                                self.execute(command.action, msg, &args);
+                            }
+                        }
+                        Err(e) => {
+                            self.outbound
+                                .reply(msg, format!("{} ({})", command.usage(), e));
+                        }
+                    }
+                }
+                return;
+            }
+        }
+        if self.is_replying(&msg.channel_login)
+            && thread_rng().gen_range(0..self.generation_for(&msg.channel_login).a_till_proc) == 0
+        {
+            self.spawn_reply(msg, self.main_kind(&msg.channel_login));
+            return;
+        }
+        if let Some((name, _)) = &tokens {
+            if self.has_role(msg, Role::Everyone)
+                && self.has_personal_model(&name[1..])
+                && self.check_cooldown(msg, cooldown::PERSONAL)
+            {
+                self.spawn_reply(msg, ModelKind::Personal(name[1..].to_owned()));
+            }
+        }
+    }
+
+    /// Returns whether `msg` may run `command` now, telling the sender about
+    /// the cooldown if configured to.
+    pub fn check_cooldown(&mut self, msg: &PrivmsgMessage, command: &str) -> bool {
+        if self.cooldowns.config.exempt_moderators && self.is_privileged(msg) {
+            return true;
+        }
+        match self
+            .cooldowns
+            .check(&msg.channel_login, command, &msg.sender.login)
+        {
+            Verdict::Allowed => true,
+            Verdict::Notify(remaining) => {
+                let reply = format!("{} is on cooldown ({}s)", command, remaining.as_secs() + 1);
+                self.outbound.reply(msg, reply);
+                false
+            }
+            Verdict::Drop => false,
+        }
+    }
+
+    pub fn has_personal_model(&self, name: &str) -> bool {
+        self.models()
+            .personal_markov
+            .as_ref()
+            .is_some_and(|m| m.contains_key(name))
+    }
+
+    pub fn execute(&mut self, action: Action, msg: &PrivmsgMessage, args: &CommandArgs) {
+        match action {
+            Action::Stair | Action::StairTo => {
+                let channel = args.get("channel").unwrap_or(&msg.channel_login);
+                let length = args.number("length").unwrap_or(0);
+                let text = args.get("text").unwrap_or_default();
+                if let Err(e) = self.say_stair(channel, length, text) {
+                    self.outbound.reply(msg, e);
+                }
+            }
+            Action::On => {
+                self.replying.insert(msg.channel_login.to_owned(), true);
+            }
+            Action::Off => {
+                self.replying.insert(msg.channel_login.to_owned(), false);
+            }
+            Action::Join => self.join(args.get("channel").unwrap_or_default()),
+            Action::Leave => self.leave(args.get("channel").unwrap_or_default()),
+            Action::ListMods => {
+                let reply = format!("Moderators: {}", list(&self.moderators));
+                self.outbound.reply(msg, reply);
+            }
+            Action::ListOwners => {
+                let reply = format!("Owners: {}", list(&self.owners));
+                self.outbound.reply(msg, reply);
+            }
+            Action::AddMod => {
+                let name = user_name(args.get("name").unwrap_or_default());
+                let reply = if self.moderators.insert(name.to_owned()) {
+                    self.save_state();
+                    format!("Sucessfully added {} to moderators", name)
+                } else {
+                    format!("{} is already moderator", name)
+                };
+                self.outbound.reply(msg, reply);
+            }
+            Action::RemMod => {
+                let name = user_name(args.get("name").unwrap_or_default());
+                let reply = if self.moderators.remove(&name) {
+                    self.save_state();
+                    format!("Sucessfully removed {} from moderators", name)
+                } else {
+                    format!("{} is already non-moderator", name)
+                };
+                self.outbound.reply(msg, reply);
+            }
+            Action::Reset => match args.get("all") {
+                Some("all") => self.reset_learning(None),
+                _ => self.reset_learning(Some(&msg.channel_login)),
+            },
+            Action::Save => self.spawn_save(Some(msg)),
+            Action::Settings => {
+                self.handle_command_setting(msg, args.get("settings").unwrap_or_default())
+            }
+            Action::Info => self.handle_command_info(msg, args.get("name")),
+            Action::Sglypa => self.spawn_reply(msg, self.main_kind(&msg.channel_login)),
+            Action::Nglypa => match args.get("all") {
+                Some("all") => self.spawn_reply(msg, ModelKind::Combined),
+                _ => self.spawn_reply(msg, ModelKind::Live(msg.channel_login.to_owned())),
+            },
+            Action::Diag => self.spawn_diag(msg, args.get("model")),
+        }
+    }
+
+    /// Forgets what was learned live in `channel`, or in every channel
+    /// including the combined model if `None`.
+    pub fn reset_learning(&mut self, channel: Option<&str>) {
+        self.pending_changes
+            .push(ModelChange::Reset(channel.map(str::to_owned)));
+        self.apply_pending();
+    }
+
+    /// Starts the combined live model over, or drops it if it's disabled.
+    fn reset_combined(&self, models: &mut Models) {
+        if self.combined_live {
+            update_markov(models.nmarkov.insert(Markov::new()), &self.generation);
+            let _ = models.ntrain_data.insert(Vec::new());
+        } else {
+            models.nmarkov = None;
+            models.ntrain_data = None;
+        }
+    }
+
+    /// Makes the combined live model match `combined_live` after loading a
+    /// snapshot that was taken with a different setting.
+    pub fn prepare_combined(&mut self) {
+        let mut models = self.models_mut();
+        if models.nmarkov.is_some() != self.combined_live {
+            self.reset_combined(&mut models);
+            drop(models);
+            self.pool.invalidate(Some(&ModelKind::Combined));
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Handles `!settings [show | reset | KEY VALUE... [FILTER TEXT]]`,
+    /// replying with the resulting settings and anything that couldn't be
+    /// set, including settings a channel profile conflicts with. `FILTER`
+    /// takes the rest of the line, nothing to clear it.
+    pub fn handle_command_setting(&mut self, msg: &PrivmsgMessage, settings: &str) {
+        let mut errors = Vec::new();
+        match settings.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] | ["show"] => {}
+            ["reset"] => {
+                if let Err(e) = self.set_generation(self.default_generation.clone()) {
+                    errors.push(e);
+                }
+            }
+            _ => {
+                let mut generation = self.generation.clone();
+                let mut rest = settings.trim();
+                while !rest.is_empty() {
+                    let before = generation.clone();
+                    let (key, after) = split_word(rest);
+                    let result = if key.eq_ignore_ascii_case("FILTER") {
+                        rest = "";
+                        generation.set(key, after)
+                    } else {
+                        let (value, after) = split_word(after);
+                        rest = after;
+                        if value.is_empty() {
+                            Err(SettingError::MissingValue(key.to_owned()))
+                        } else {
+                            generation.set(key, value)
+                        }
+                    };
+                    let result = result.map_err(|e| e.to_string()).and_then(|_| {
+                        validate_profiles(&self.profiles, &generation)
+                            .map_err(|e| format!("{} conflicts with {}", key, e))
+                    });
+                    if let Err(e) = result {
+                        generation = before;
+                        errors.push(e);
+                    }
+                }
+                if let Err(e) = self.set_generation(generation) {
+                    errors.push(e);
+                }
+            }
+        }
+        let mut reply = self.generation.show();
+        if !errors.is_empty() {
+            reply = format!("{} (failed: {})", reply, errors.join("; "));
+        }
+        self.outbound.reply(msg, reply);
+    }
+
+    /// Switches to `generation`, applying it to the built models and
+    /// dropping replies generated with the old settings. Nothing changes if
+    /// a channel profile would make it invalid.
+    pub fn set_generation(&mut self, generation: GenerationConfig) -> Result<(), String> {
+        if generation == self.generation {
+            return Ok(());
+        }
+        validate_profiles(&self.profiles, &generation)?;
+        self.generation = generation;
+        self.pending_changes.push(ModelChange::Settings);
+        self.apply_pending();
+        self.pool.set_generation(self.generation.clone());
+        info!("settings: {}", self.generation.show());
+        Ok(())
+    }
+
+    pub fn handle_command_info(&mut self, msg: &PrivmsgMessage, name: Option<&str>) {
+        if let Some(name) = name {
+            self.outbound.say(
+                &msg.channel_login,
+                format!(
+                    "@{} Found {} relevant messages from {}",
+                    msg.sender.name,
+                    self.models()
+                        .personal_train_data
+                        .as_ref()
+                        .map(|d| d.get(&user_name(name)).map(|arr| arr.len()).unwrap_or(0))
+                        .unwrap_or(0),
+                    name,
+                ),
+            );
+        } else if let Some(personal_train_data) = &self.models().personal_train_data {
+            let mut top = personal_train_data
+                .iter()
+                .map(|(k, v)| (k, v.len()))
+                .collect::<Vec<(&String, usize)>>();
+            top.sort_by_key(|(_k, v)| *v);
+            top.reverse();
+            let num = 20;
+            let ans = top
+                .iter()
+                .take(num)
+                .map(|(k, v)| format!("{}: {};\t", k, v))
+                .collect::<String>();
+            self.outbound.say(
+                &msg.channel_login,
+                format!("@{} Top {} chatters:\t{}", msg.sender.name, num, ans),
+            );
+        }
+    }
+
+    /// Replies to `msg` with a ready reply of the `kind` model, or generates
+    /// one on a blocking worker so chat keeps being handled meanwhile. The
+    /// reply is dropped if it takes longer than `timeout_ms`.
+    pub fn spawn_reply(&self, msg: &PrivmsgMessage, kind: ModelKind) {
+        let config = self.generation_for(&msg.channel_login);
+        let pooled = if config.generates_like(&self.generation) {
+            self.pool.take(&kind)
+        } else {
+            None
+        };
+        let models = self.models.clone();
+        let outbound = self.outbound.clone();
+        let msg = msg.clone();
+        let timeout = Duration::from_millis(config.timeout_ms);
+        tokio::spawn(async move {
+            let deadline = Instant::now() + timeout;
+            let job = tokio::task::spawn_blocking(move || {
+                let models = models.read().unwrap_or_else(PoisonError::into_inner);
+                let result = match pooled {
+                    Some(result) => result,
+                    None => generate(models.get(&kind)?, &config, &kind, deadline).ok()?,
+                };
+                info!("{:?}", result);
+                let show_refs = config.show_refs && !matches!(kind, ModelKind::Personal(_));
+                Some(if show_refs {
+                    let refs = models
+                        .authors(&kind, &result)
+                        .iter()
+                        .map(|name| format!("{},", name))
+                        .collect::<String>();
+                    format!("Sglypa: {} [{}]", result.text, refs)
+                } else {
+                    format!("Sglypa: {}", result.text)
+                })
+            });
+            match tokio::time::timeout(timeout, job).await {
+                Ok(Ok(Some(reply))) => outbound.reply(&msg, reply),
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => error!("generation failed: {}", e),
+                Err(_) => info!("generation for {} timed out", msg.message_id),
+            }
+        });
+    }
+
+    /// Model named by a command argument: `main` (or `sglypa`) for the model
+    /// `!sglypa` uses in `channel`, `live` (or `nglypa`) for the live model of
+    /// `channel`, or any name `ModelKind::parse` knows.
+    fn model_kind(&self, name: &str, channel: &str) -> ModelKind {
+        match name.to_lowercase().as_ref() {
+            "main" | "sglypa" => self.main_kind(channel),
+            "live" | "nglypa" => ModelKind::Live(channel.to_owned()),
+            "all" => ModelKind::Combined,
+            _ => ModelKind::parse(name),
+        }
+    }
+
+    /// Reports how the current settings fare against `DIAG_ATTEMPTS` raw
+    /// candidates of the model called `model`, on a blocking worker.
+    pub fn spawn_diag(&self, msg: &PrivmsgMessage, model: Option<&str>) {
+        let kind = self.model_kind(model.unwrap_or("main"), &msg.channel_login);
+        let models = self.models.clone();
+        let config = self.generation_for(&msg.channel_login);
+        let outbound = self.outbound.clone();
+        let msg = msg.clone();
+        tokio::task::spawn_blocking(move || {
+            let deadline = Instant::now() + Duration::from_millis(config.timeout_ms);
+            let models = models.read().unwrap_or_else(PoisonError::into_inner);
+            let reply = match models.get(&kind) {
+                Some(markov) => match diagnose(markov, &config, DIAG_ATTEMPTS, deadline) {
+                    Ok(diagnostics) => format!("{}: {}", kind, diagnostics),
+                    Err(e) => format!("{}: {:?}", kind, e),
+                },
+                None => format!("No model {}", kind),
+            };
+            outbound.reply(&msg, reply);
+        });
+    }
+
+    /// Learns `msg`, or keeps it for later if a generation is holding the
+    /// models.
+    pub fn handle_learn(&mut self, msg: &PrivmsgMessage) {
+        if !learn_filter(msg.sender.name.to_owned(), msg.message_text.to_owned()) {
+            return;
+        }
+        let data = InputData {
+            text: msg.message_text.to_owned(),
+            meta: Some(msg.sender.login.to_owned()),
+        };
+        self.pending_changes
+            .push(ModelChange::Learn(msg.channel_login.to_owned(), data));
+        self.apply_pending();
+    }
+
+    /// Makes the changes held back because generations were holding the
+    /// models, unless one still is. `run` retries every `PENDING_RETRY`,
+    /// with the reply pools paused meanwhile so they don't keep holding them.
+    pub fn apply_pending(&mut self) {
+        if self.pending_changes.is_empty() {
+            return;
+        }
+        let lock = self.models.clone();
+        let Ok(mut models) = lock.try_write() else {
+            self.pool.pause(true);
+            return;
+        };
+        self.pool.pause(false);
+        for change in std::mem::take(&mut self.pending_changes) {
+            match change {
+                ModelChange::Learn(channel, data) => {
+                    let corpus = self.corpora.get(&channel).map(String::as_str);
+                    models.learn(&channel, corpus, data, &self.generation);
+                    self.dirty.store(true, Ordering::Relaxed);
+                }
+                ModelChange::Reset(Some(channel)) => {
+                    models.live_markov.remove(&channel);
+                    models.live_train_data.remove(&channel);
+                    self.pool.invalidate(Some(&ModelKind::Live(channel)));
+                    self.dirty.store(true, Ordering::Relaxed);
+                }
+                ModelChange::Reset(None) => {
+                    models.live_markov.clear();
+                    models.live_train_data.clear();
+                    self.reset_combined(&mut models);
+                    self.pool.invalidate(None);
+                    self.dirty.store(true, Ordering::Relaxed);
+                }
+                ModelChange::Settings => models.apply_settings(&self.generation),
+            }
+        }
+    }
+}
+
+fn list(names: &HashSet<String>) -> String {
+    names.iter().map(|s| format!("{}, ", s)).collect::<String>()
+}
+
+/// Splits the first word off `text`, returning it and the rest.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Turns `@Name` into the login `name`.
+fn user_name(name: &str) -> String {
+    name.trim_start_matches('@').to_lowercase()
+}
+
+pub fn learn_filter(_name: String, body: String) -> bool {
+    !body.contains("Tier")
+        && !body.to_lowercase().contains("sglypa")
+        && !body.starts_with("!")
+        && body.split(" ").count() >= STATE_SIZE
+        && !body.to_lowercase().contains("-.-.-")
+        && !body.is_ascii()
+    // && (!name.to_lowercase().eq("kabachoke") || (!body.to_lowercase().contains(":confident:") && !body.to_lowercase().contains("мы зависли")))
+}
+
+/// Loads the snapshot, or trains from the vods if there is none, and trains
+/// the corpora missing from it.
+pub fn prepare_models(sglypa: &mut Sglypa, args: &Args, config: &Config) -> Result<(), String> {
+    let loaded = !args.retrain
+        && config.snapshot.exists()
+        && sglypa
+            .load_snapshot()
+            .map_err(|e| error!("{}, retraining", e))
+            .is_ok();
+    if !loaded {
+        sglypa.train_from_vods(
+            &config.vods_dir,
+            &config.streamers,
+            config.personal,
+            Some(learn_filter),
+        )?;
+        if let Err(e) = sglypa.save_snapshot() {
+            error!("{}", e);
+        }
+    }
+    if sglypa.train_corpora(&config.vods_dir, Some(learn_filter))? {
+        if let Err(e) = sglypa.save_snapshot() {
+            error!("{}", e);
+        }
+    }
+    Ok(())
+}
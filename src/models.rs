@@ -0,0 +1,320 @@
+use crate::files::write_atomically;
+use crate::generation::{apply_settings, update_markov, GenerationConfig};
+use crate::vods::read_vods;
+
+use log::info;
+use markov_strings::{InputData, Markov, MarkovResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+const SNAPSHOT_MAGIC: &[u8; 6] = b"SGLYPA";
+/// Bump whenever `Models` (or a type inside it) changes shape.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Which of the models to talk with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ModelKind {
+    /// Trained on the VODs and everything said in chat since
+    Main,
+    /// Trained on the VODs of some streamers and the chat of the channels
+    /// mapped to them, by corpus name
+    Corpus(String),
+    /// Trained only on the chat of one channel since the last `!reset`
+    Live(String),
+    /// Trained only on the chat of every channel since the last `!reset all`
+    Combined,
+    /// Trained on a single chatter
+    Personal(String),
+}
+
+impl fmt::Display for ModelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelKind::Main => write!(f, "main"),
+            ModelKind::Corpus(corpus) => write!(f, "vods:{}", corpus),
+            ModelKind::Live(channel) => write!(f, "#{}", channel),
+            ModelKind::Combined => write!(f, "combined"),
+            ModelKind::Personal(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl ModelKind {
+    /// Reads a model name as written by `Display`.
+    pub fn parse(name: &str) -> ModelKind {
+        let name = name.trim_start_matches('@').to_lowercase();
+        if let Some(channel) = name.strip_prefix('#') {
+            return ModelKind::Live(channel.to_owned());
+        }
+        if let Some(corpus) = name.strip_prefix("vods:") {
+            return ModelKind::Corpus(corpus.to_owned());
+        }
+        match name.as_ref() {
+            "main" => ModelKind::Main,
+            "combined" => ModelKind::Combined,
+            _ => ModelKind::Personal(name),
+        }
+    }
+}
+
+/// All models the bot talks with, together with the corpora they were built
+/// from, so they can be saved and restored as one snapshot.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Models {
+    pub markov: Option<Markov>,
+    /// The combined live model, if enabled
+    pub nmarkov: Option<Markov>,
+    pub personal_markov: Option<HashMap<String, Markov>>,
+    /// Live models by channel
+    pub live_markov: HashMap<String, Markov>,
+    pub corpus_markov: HashMap<String, Markov>,
+    pub train_data: Option<Vec<InputData>>,
+    pub ntrain_data: Option<Vec<InputData>>,
+    pub personal_train_data: Option<HashMap<String, Vec<InputData>>>,
+    pub live_train_data: HashMap<String, Vec<InputData>>,
+    pub corpus_train_data: HashMap<String, Vec<InputData>>,
+}
+
+impl Models {
+    /// Writes a snapshot to `path`. The snapshot is written to a temporary
+    /// file first, so a failed save never clobbers the previous one.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        write_atomically(
+            path,
+            false,
+            |writer| -> Result<(), Box<dyn std::error::Error>> {
+                writer.write_all(SNAPSHOT_MAGIC)?;
+                writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+                bincode::serialize_into(writer, self)?;
+                Ok(())
+            },
+        )
+        .map_err(|e| format!("failed to save {}: {}", path.display(), e))
+    }
+
+    /// Reads a snapshot written by `save` and applies `generation` to every
+    /// model in it.
+    pub fn load(path: &Path, generation: &GenerationConfig) -> Result<Models, String> {
+        let read = || -> Result<Models, Box<dyn std::error::Error>> {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut magic = [0u8; 6];
+            reader.read_exact(&mut magic)?;
+            if &magic != SNAPSHOT_MAGIC {
+                return Err("not a snapshot".into());
+            }
+            let mut version = [0u8; 4];
+            reader.read_exact(&mut version)?;
+            match u32::from_le_bytes(version) {
+                SNAPSHOT_VERSION => Ok(bincode::deserialize_from(reader)?),
+                version => Err(format!(
+                    "snapshot version {} is not supported, expected {}",
+                    version, SNAPSHOT_VERSION
+                )
+                .into()),
+            }
+        };
+        let mut models = read().map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+        models.apply_settings(generation);
+        Ok(models)
+    }
+
+    pub fn get(&self, kind: &ModelKind) -> Option<&Markov> {
+        match kind {
+            ModelKind::Main => self.markov.as_ref(),
+            ModelKind::Corpus(corpus) => self.corpus_markov.get(corpus),
+            ModelKind::Live(channel) => self.live_markov.get(channel),
+            ModelKind::Combined => self.nmarkov.as_ref(),
+            ModelKind::Personal(name) => self.personal_markov.as_ref()?.get(name),
+        }
+    }
+
+    pub fn train_data(&self, kind: &ModelKind) -> Option<&Vec<InputData>> {
+        match kind {
+            ModelKind::Main => self.train_data.as_ref(),
+            ModelKind::Corpus(corpus) => self.corpus_train_data.get(corpus),
+            ModelKind::Live(channel) => self.live_train_data.get(channel),
+            ModelKind::Combined => self.ntrain_data.as_ref(),
+            ModelKind::Personal(name) => self.personal_train_data.as_ref()?.get(name),
+        }
+    }
+
+    /// Authors of the messages `result` was built from, in the order of
+    /// `result.refs`.
+    pub fn authors(&self, kind: &ModelKind, result: &MarkovResult) -> Vec<String> {
+        let Some(data) = self.train_data(kind) else {
+            return Vec::new();
+        };
+        result
+            .refs
+            .iter()
+            .filter_map(|&id| data.get(id)?.meta.to_owned())
+            .collect()
+    }
+
+    /// Adds a chat message from `channel` to every model it belongs to,
+    /// starting the channel's live model if needed. `corpus` is the corpus
+    /// the channel is mapped to, and `data.meta` the login of the author.
+    pub fn learn(
+        &mut self,
+        channel: &str,
+        corpus: Option<&str>,
+        data: InputData,
+        generation: &GenerationConfig,
+    ) {
+        if let (Some(markov), Some(train_data)) = (self.markov.as_mut(), self.train_data.as_mut()) {
+            train_data.push(data.clone());
+            markov.add_to_corpus(vec![data.clone()]);
+        }
+        if let Some(corpus) = corpus {
+            if let (Some(markov), Some(train_data)) = (
+                self.corpus_markov.get_mut(corpus),
+                self.corpus_train_data.get_mut(corpus),
+            ) {
+                train_data.push(data.clone());
+                markov.add_to_corpus(vec![data.clone()]);
+            }
+        }
+        if let (Some(name), Some(personal_markov), Some(personal_train_data)) = (
+            data.meta.as_ref(),
+            self.personal_markov.as_mut(),
+            self.personal_train_data.as_mut(),
+        ) {
+            if let Some(train_data) = personal_train_data.get_mut(name) {
+                train_data.push(data.clone());
+            }
+            if let Some(markov) = personal_markov.get_mut(name) {
+                markov.add_to_corpus(vec![data.clone()]);
+            }
+        }
+        let live_markov = self
+            .live_markov
+            .entry(channel.to_owned())
+            .or_insert_with(|| {
+                let mut m = Markov::new();
+                update_markov(&mut m, generation);
+                m
+            });
+        live_markov.add_to_corpus(vec![data.clone()]);
+        self.live_train_data
+            .entry(channel.to_owned())
+            .or_default()
+            .push(data.clone());
+        if let (Some(nmarkov), Some(ntrain_data)) =
+            (self.nmarkov.as_mut(), self.ntrain_data.as_mut())
+        {
+            ntrain_data.push(data.clone());
+            nmarkov.add_to_corpus(vec![data]);
+        }
+    }
+
+    /// Trains the main (and optionally personal) models on the chat logs in
+    /// `vods_dir/<streamer>`, using every streamer folder if `streamers` is
+    /// empty. With `strict`, fails without changing the models if a chat
+    /// log can't be read.
+    pub fn train_main(
+        &mut self,
+        vods_dir: &Path,
+        streamers: &[String],
+        personal: bool,
+        message_filter: Option<fn(String, String) -> bool>,
+        strict: bool,
+        generation: &GenerationConfig,
+    ) -> Result<(), String> {
+        let mut train_data = Vec::new();
+        let mut personal_train_data = HashMap::<String, Vec<InputData>>::new();
+        read_vods(vods_dir, streamers, message_filter, strict, |name, body| {
+            let data = InputData {
+                text: body,
+                meta: Some(name.clone()),
+            };
+            if personal {
+                personal_train_data
+                    .entry(name)
+                    .or_default()
+                    .push(data.clone());
+            }
+            train_data.push(data);
+        })?;
+        info!("{} total training messages", train_data.len());
+        let markov = build_markov(&mut train_data, generation);
+        info!("{} deduped training messages", train_data.len());
+        info!("trained main");
+        self.markov = Some(markov);
+        self.train_data = Some(train_data);
+        self.personal_markov = personal.then(|| {
+            personal_train_data
+                .iter_mut()
+                .map(|(name, data)| (name.to_owned(), build_markov(data, generation)))
+                .collect()
+        });
+        self.personal_train_data = personal.then_some(personal_train_data);
+        Ok(())
+    }
+
+    /// Trains the model of the corpus called `corpus` on the chat logs in
+    /// `vods_dir/<streamer>` of `streamers`, see `train_main`.
+    pub fn train_corpus(
+        &mut self,
+        corpus: &str,
+        vods_dir: &Path,
+        streamers: &[String],
+        message_filter: Option<fn(String, String) -> bool>,
+        strict: bool,
+        generation: &GenerationConfig,
+    ) -> Result<(), String> {
+        let mut train_data = Vec::new();
+        read_vods(vods_dir, streamers, message_filter, strict, |name, body| {
+            train_data.push(InputData {
+                text: body,
+                meta: Some(name),
+            })
+        })?;
+        let markov = build_markov(&mut train_data, generation);
+        info!("trained {}, {} messages", corpus, train_data.len());
+        self.corpus_markov.insert(corpus.to_owned(), markov);
+        self.corpus_train_data.insert(corpus.to_owned(), train_data);
+        Ok(())
+    }
+
+    /// Applies the settings that can change after a model was built.
+    pub fn apply_settings(&mut self, generation: &GenerationConfig) {
+        let personal = self.personal_markov.iter_mut().flat_map(|m| m.values_mut());
+        for m in self
+            .markov
+            .iter_mut()
+            .chain(self.nmarkov.iter_mut())
+            .chain(personal)
+            .chain(self.live_markov.values_mut())
+            .chain(self.corpus_markov.values_mut())
+        {
+            apply_settings(m, generation);
+        }
+    }
+}
+
+/// Dedups `data` and builds a model out of it.
+fn build_markov(data: &mut Vec<InputData>, generation: &GenerationConfig) -> Markov {
+    sort_dedup(data);
+    let mut markov = Markov::new();
+    update_markov(&mut markov, generation);
+    markov.add_to_corpus(data.clone());
+    markov
+}
+
+/// `InputData` isn't `Ord`, so order by text and then author.
+pub fn sort_dedup(data: &mut Vec<InputData>) {
+    data.sort_by(|a, b| (&a.text, &a.meta).cmp(&(&b.text, &b.meta)));
+    data.dedup();
+}
+
+/// Name of the corpus trained on `streamers`.
+pub fn corpus_name(streamers: &[String]) -> String {
+    let mut streamers = streamers.to_vec();
+    streamers.sort();
+    streamers.dedup();
+    streamers.join("+")
+}
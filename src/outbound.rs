@@ -0,0 +1,755 @@
+use crate::chat::ChatSink;
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use twitch_irc::message::PrivmsgMessage;
+
+/// Appended to every other repetition of a message so Twitch doesn't reject
+/// it as a duplicate. U+E0000 is invisible in chat.
+const DUPLICATE_SUFFIX: &str = " \u{E0000}";
+
+/// Twitch rejects a message identical to the previous one sent to the same
+/// channel within this window.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+
+/// A NOTICE arriving later than this after a send isn't about that message.
+const NOTICE_WINDOW: Duration = Duration::from_secs(5);
+
+/// A bucket of `capacity` tokens gaining one token every `refill_ms`.
+///
+/// Twitch counts messages in a sliding 30 second window, so a bucket only
+/// stays under a limit of N if `capacity` plus the refill over 30 seconds
+/// is at most N.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub capacity: u32,
+    pub refill_ms: u64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboundConfig {
+    /// Every message, Twitch allows 20 per 30 seconds
+    pub global: BucketConfig,
+    /// Every message including those to channels where the bot is mod or
+    /// VIP, Twitch allows 100 per 30 seconds
+    pub privileged_global: BucketConfig,
+    /// Per channel where the bot is a regular user, Twitch allows one per second
+    pub channel: BucketConfig,
+    /// Per channel where the bot is mod or VIP
+    pub privileged_channel: BucketConfig,
+    /// Messages queued per channel before new ones are dropped
+    pub max_queue: usize,
+    /// How long to stop sending to a channel after Twitch said `msg_ratelimit`
+    pub backoff_ms: u64,
+    /// How often a message rejected by Twitch is sent again before giving up
+    pub max_retries: u32,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self {
+            global: BucketConfig {
+                capacity: 10,
+                refill_ms: 3000,
+            },
+            privileged_global: BucketConfig {
+                capacity: 50,
+                refill_ms: 600,
+            },
+            channel: BucketConfig {
+                capacity: 1,
+                refill_ms: 1100,
+            },
+            privileged_channel: BucketConfig {
+                capacity: 20,
+                refill_ms: 50,
+            },
+            max_queue: 50,
+            backoff_ms: 5000,
+            max_retries: 2,
+        }
+    }
+}
+
+impl OutboundConfig {
+    /// Limits that never hold a message back, for chats other than Twitch.
+    pub fn unlimited() -> Self {
+        let unlimited = BucketConfig {
+            capacity: u32::MAX,
+            refill_ms: 1,
+        };
+        Self {
+            global: unlimited,
+            privileged_global: unlimited,
+            channel: unlimited,
+            privileged_channel: unlimited,
+            max_queue: usize::MAX,
+            ..Self::default()
+        }
+    }
+}
+
+struct TokenBucket {
+    config: BucketConfig,
+    tokens: u32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(config: BucketConfig) -> Self {
+        Self {
+            config,
+            tokens: config.capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let refill = Duration::from_millis(self.config.refill_ms.max(1));
+        while self.tokens < self.config.capacity && now >= self.last_refill + refill {
+            self.tokens += 1;
+            self.last_refill += refill;
+        }
+        if self.tokens == self.config.capacity {
+            self.last_refill = now;
+        }
+    }
+
+    /// How long until a token is available, zero if there is one now.
+    fn wait(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens > 0 {
+            return Duration::ZERO;
+        }
+        (self.last_refill + Duration::from_millis(self.config.refill_ms.max(1)))
+            .saturating_duration_since(now)
+    }
+
+    fn take(&mut self) {
+        self.tokens = self.tokens.saturating_sub(1);
+    }
+}
+
+#[derive(Debug)]
+pub struct OutMessage {
+    pub channel: String,
+    pub text: String,
+    /// Id of the message this one replies to
+    pub reply_to: Option<String>,
+    /// How often Twitch already rejected this message
+    retries: u32,
+}
+
+enum Outgoing {
+    Message(OutMessage),
+    /// Messages to one channel that are queued or dropped together
+    Batch(Vec<OutMessage>),
+    Privileged(String, bool),
+    Notice(String, String),
+}
+
+/// The last message sent to a channel.
+struct Sent {
+    message: OutMessage,
+    /// Whether `DUPLICATE_SUFFIX` was appended
+    varied: bool,
+    at: Instant,
+}
+
+/// Handle to the outbound queue. Every message the bot sends goes through
+/// here and is delayed as needed to stay within Twitch's rate limits.
+#[derive(Clone)]
+pub struct Outbound {
+    sender: mpsc::UnboundedSender<Outgoing>,
+    max_queue: usize,
+    /// Closed once the queue task ends, see `finish`
+    finished: watch::Receiver<()>,
+}
+
+impl Outbound {
+    /// Starts the task sending queued messages to `sink`.
+    pub fn spawn(sink: Arc<dyn ChatSink>, config: OutboundConfig) -> Outbound {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (finish, finished) = watch::channel(());
+        let max_queue = config.max_queue;
+        tokio::spawn(async move {
+            Queue::new(sink, config).run(receiver).await;
+            drop(finish);
+        });
+        Outbound {
+            sender,
+            max_queue,
+            finished,
+        }
+    }
+
+    /// Drops this handle and waits until every other one is dropped too and
+    /// everything queued is sent.
+    pub async fn finish(self) {
+        let mut finished = self.finished.clone();
+        drop(self);
+        while finished.changed().await.is_ok() {}
+    }
+
+    /// Messages queued per channel before new ones are dropped.
+    pub fn max_queue(&self) -> usize {
+        self.max_queue
+    }
+
+    pub fn say(&self, channel: &str, text: String) {
+        self.send(Outgoing::Message(OutMessage {
+            channel: channel.to_owned(),
+            text,
+            reply_to: None,
+            retries: 0,
+        }));
+    }
+
+    /// Says every one of `texts` in `channel`, or none of them if they don't
+    /// fit in its queue.
+    pub fn say_all(&self, channel: &str, texts: Vec<String>) {
+        let messages = texts
+            .into_iter()
+            .map(|text| OutMessage {
+                channel: channel.to_owned(),
+                text,
+                reply_to: None,
+                retries: 0,
+            })
+            .collect();
+        self.send(Outgoing::Batch(messages));
+    }
+
+    pub fn reply(&self, msg: &PrivmsgMessage, text: String) {
+        self.send(Outgoing::Message(OutMessage {
+            channel: msg.channel_login.to_owned(),
+            text,
+            reply_to: Some(msg.message_id.to_owned()),
+            retries: 0,
+        }));
+    }
+
+    /// Tells the queue whether the bot is mod or VIP in `channel`, which
+    /// lifts most of the limits there.
+    pub fn set_privileged(&self, channel: &str, privileged: bool) {
+        self.send(Outgoing::Privileged(channel.to_owned(), privileged));
+    }
+
+    /// Passes on a NOTICE from Twitch so rejected messages can be retried.
+    pub fn notice(&self, channel: &str, message_id: &str) {
+        self.send(Outgoing::Notice(channel.to_owned(), message_id.to_owned()));
+    }
+
+    fn send(&self, outgoing: Outgoing) {
+        if self.sender.send(outgoing).is_err() {
+            error!("outbound queue is gone");
+        }
+    }
+}
+
+struct Queue {
+    sink: Arc<dyn ChatSink>,
+    config: OutboundConfig,
+    messages: VecDeque<OutMessage>,
+    privileged: HashSet<String>,
+    global: TokenBucket,
+    privileged_global: TokenBucket,
+    channels: HashMap<String, TokenBucket>,
+    /// Channels not to send to until the given time
+    paused: HashMap<String, Instant>,
+    last_sent: HashMap<String, Sent>,
+}
+
+impl Queue {
+    fn new(sink: Arc<dyn ChatSink>, config: OutboundConfig) -> Self {
+        Self {
+            sink,
+            global: TokenBucket::new(config.global),
+            privileged_global: TokenBucket::new(config.privileged_global),
+            config,
+            messages: VecDeque::new(),
+            privileged: HashSet::new(),
+            channels: HashMap::new(),
+            paused: HashMap::new(),
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Sends what is queued until every `Outbound` is dropped and nothing is
+    /// left to send.
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<Outgoing>) {
+        let mut open = true;
+        loop {
+            while let Ok(outgoing) = receiver.try_recv() {
+                self.push(outgoing);
+            }
+            if self.messages.is_empty() {
+                match receiver.recv().await {
+                    Some(outgoing) => self.push(outgoing),
+                    None => break,
+                }
+                continue;
+            }
+            match self.next_ready(Instant::now()) {
+                Ok(index) => {
+                    let message = self.messages.remove(index).unwrap();
+                    self.send(message).await;
+                }
+                Err(wait) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        outgoing = receiver.recv(), if open => match outgoing {
+                            Some(outgoing) => self.push(outgoing),
+                            None => open = false,
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, outgoing: Outgoing) {
+        match outgoing {
+            Outgoing::Message(message) => self.enqueue(vec![message]),
+            Outgoing::Batch(messages) => self.enqueue(messages),
+            Outgoing::Privileged(channel, privileged) => {
+                let changed = if privileged {
+                    self.privileged.insert(channel.to_owned())
+                } else {
+                    self.privileged.remove(&channel)
+                };
+                if changed {
+                    info!("privileged in {}: {}", channel, privileged);
+                    self.channels.remove(&channel);
+                }
+            }
+            Outgoing::Notice(channel, message_id) => {
+                self.handle_notice(channel, &message_id, Instant::now())
+            }
+        }
+    }
+
+    /// Queues `messages` to one channel, or drops all of them if they don't
+    /// fit in its queue.
+    fn enqueue(&mut self, messages: Vec<OutMessage>) {
+        let Some(channel) = messages.first().map(|m| m.channel.to_owned()) else {
+            return;
+        };
+        let queued = self
+            .messages
+            .iter()
+            .filter(|m| m.channel == channel)
+            .count();
+        if queued + messages.len() > self.config.max_queue {
+            info!(
+                "outbound queue for {} is full, dropping {} messages",
+                channel,
+                messages.len()
+            );
+            return;
+        }
+        self.messages.extend(messages);
+    }
+
+    /// Sends the last message to `channel` again if Twitch rejected it, after
+    /// a pause if it was rejected for going too fast.
+    fn handle_notice(&mut self, channel: String, message_id: &str, now: Instant) {
+        let backoff = match message_id {
+            "msg_duplicate" => false,
+            "msg_ratelimit" => true,
+            _ => return,
+        };
+        if backoff {
+            warn!("rate limited in {}, backing off", channel);
+            let until = now + Duration::from_millis(self.config.backoff_ms);
+            self.paused.insert(channel.to_owned(), until);
+            if let Some(bucket) = self.channels.get_mut(&channel) {
+                bucket.tokens = 0;
+            }
+        } else {
+            warn!("duplicate message rejected in {}", channel);
+        }
+        let Some(sent) = self.last_sent.get(&channel) else {
+            return;
+        };
+        if now.saturating_duration_since(sent.at) > NOTICE_WINDOW
+            || sent.message.retries >= self.config.max_retries
+        {
+            return;
+        }
+        let message = OutMessage {
+            channel: sent.message.channel.to_owned(),
+            text: sent.message.text.to_owned(),
+            reply_to: sent.message.reply_to.to_owned(),
+            retries: sent.message.retries + 1,
+        };
+        // Anything queued for the channel was queued after the rejected
+        // message, so retrying it first keeps the order.
+        let index = self
+            .messages
+            .iter()
+            .position(|m| m.channel == channel)
+            .unwrap_or(self.messages.len());
+        self.messages.insert(index, message);
+    }
+
+    /// Finds the oldest message that can be sent right now without
+    /// reordering messages within a channel, or how long to wait otherwise.
+    fn next_ready(&mut self, now: Instant) -> Result<usize, Duration> {
+        let mut seen = HashSet::new();
+        let mut wait = Duration::MAX;
+        for (index, message) in self.messages.iter().enumerate() {
+            if !seen.insert(message.channel.as_str()) {
+                continue;
+            }
+            let privileged = self.privileged.contains(&message.channel);
+            let config = if privileged {
+                self.config.privileged_channel
+            } else {
+                self.config.channel
+            };
+            let channel_wait = self
+                .channels
+                .entry(message.channel.to_owned())
+                .or_insert_with(|| TokenBucket::new(config))
+                .wait(now);
+            let mut global_wait = self.privileged_global.wait(now);
+            if !privileged {
+                global_wait = global_wait.max(self.global.wait(now));
+            }
+            let paused_wait = self
+                .paused
+                .get(&message.channel)
+                .map_or(Duration::ZERO, |until| until.saturating_duration_since(now));
+            let message_wait = channel_wait.max(global_wait).max(paused_wait);
+            if message_wait.is_zero() {
+                return Ok(index);
+            }
+            wait = wait.min(message_wait);
+        }
+        Err(wait)
+    }
+
+    /// Takes the tokens for sending a message to `channel`.
+    fn spend(&mut self, channel: &str) {
+        if let Some(bucket) = self.channels.get_mut(channel) {
+            bucket.take();
+        }
+        self.privileged_global.take();
+        if !self.privileged.contains(channel) {
+            self.global.take();
+        }
+        self.paused.remove(channel);
+    }
+
+    /// Whether `message` needs `DUPLICATE_SUFFIX` if sent at `now`. A
+    /// message equal to the previous one would be rejected, so every other
+    /// repetition gets the suffix.
+    fn varies(&self, message: &OutMessage, now: Instant) -> bool {
+        self.last_sent.get(&message.channel).is_some_and(|last| {
+            last.message.text == message.text
+                && now.saturating_duration_since(last.at) < DUPLICATE_WINDOW
+                && !last.varied
+        })
+    }
+
+    async fn send(&mut self, message: OutMessage) {
+        self.spend(&message.channel);
+
+        let now = Instant::now();
+        let varied = self.varies(&message, now);
+        let text = if varied {
+            message.text.to_owned() + DUPLICATE_SUFFIX
+        } else {
+            message.text.to_owned()
+        };
+
+        let result = match &message.reply_to {
+            Some(id) => self.sink.reply(&message.channel, id, text).await,
+            None => self.sink.say(&message.channel, text).await,
+        };
+        if let Err(e) = result {
+            error!("failed to send to {}: {}", message.channel, e);
+            return;
+        }
+        self.last_sent.insert(
+            message.channel.to_owned(),
+            Sent {
+                message,
+                varied,
+                at: now,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::MockChat;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn queue(config: OutboundConfig) -> Queue {
+        let (_, sink, _) = MockChat::new();
+        Queue::new(Arc::new(sink), config)
+    }
+
+    fn say(channel: &str, text: &str) -> Outgoing {
+        Outgoing::Message(OutMessage {
+            channel: channel.to_owned(),
+            text: text.to_owned(),
+            reply_to: None,
+            retries: 0,
+        })
+    }
+
+    /// Takes the next message ready at `now` off `queue` as if it was sent.
+    fn send_next(queue: &mut Queue, now: Instant) -> Result<OutMessage, Duration> {
+        let index = queue.next_ready(now)?;
+        let message = queue.messages.remove(index).unwrap();
+        queue.spend(&message.channel);
+        Ok(message)
+    }
+
+    #[tokio::test]
+    async fn finish_waits_for_the_queue_to_empty() {
+        let (mut chat, sink, _) = MockChat::new();
+        let outbound = Outbound::spawn(
+            Arc::new(sink),
+            OutboundConfig {
+                channel: BucketConfig {
+                    capacity: 1,
+                    refill_ms: 200,
+                },
+                ..OutboundConfig::unlimited()
+            },
+        );
+        let other = outbound.clone();
+        outbound.say("a", "1".to_owned());
+        outbound.say("a", "2".to_owned());
+        tokio::spawn(async move {
+            tokio::time::sleep(ms(100)).await;
+            other.say("a", "3".to_owned());
+        });
+        let start = Instant::now();
+        outbound.finish().await;
+        assert!(start.elapsed() >= ms(400), "{:?}", start.elapsed());
+        let texts = chat.drain().into_iter().map(|m| m.text);
+        assert_eq!(texts.collect::<Vec<_>>(), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn bucket_empties_and_refills_up_to_capacity() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket {
+            config: BucketConfig {
+                capacity: 2,
+                refill_ms: 1000,
+            },
+            tokens: 2,
+            last_refill: start,
+        };
+        for _ in 0..2 {
+            assert_eq!(bucket.wait(start), Duration::ZERO);
+            bucket.take();
+        }
+        assert_eq!(bucket.wait(start + ms(400)), ms(600));
+        assert_eq!(bucket.wait(start + ms(1000)), Duration::ZERO);
+        bucket.take();
+        assert_eq!(bucket.wait(start + ms(1500)), ms(500));
+        assert_eq!(bucket.wait(start + ms(10_000)), Duration::ZERO);
+        assert_eq!(bucket.tokens, 2);
+    }
+
+    #[test]
+    fn channels_wait_for_their_own_bucket() {
+        let mut queue = queue(OutboundConfig {
+            channel: BucketConfig {
+                capacity: 1,
+                refill_ms: 1000,
+            },
+            ..OutboundConfig::unlimited()
+        });
+        let start = Instant::now();
+        queue.push(say("a", "1"));
+        queue.push(say("a", "2"));
+        queue.push(say("b", "3"));
+        assert_eq!(send_next(&mut queue, start).unwrap().text, "1");
+        // `a` is out of tokens, but `b` isn't held up by it
+        assert_eq!(send_next(&mut queue, start).unwrap().text, "3");
+        assert_eq!(send_next(&mut queue, start).unwrap_err(), ms(1000));
+        assert_eq!(send_next(&mut queue, start + ms(1000)).unwrap().text, "2");
+    }
+
+    #[test]
+    fn global_bucket_is_lifted_where_privileged() {
+        let mut queue = queue(OutboundConfig {
+            global: BucketConfig {
+                capacity: 1,
+                refill_ms: 3000,
+            },
+            ..OutboundConfig::unlimited()
+        });
+        let start = Instant::now();
+        queue.push(Outgoing::Privileged("mod".to_owned(), true));
+        queue.push(say("a", "1"));
+        queue.push(say("b", "2"));
+        queue.push(say("mod", "3"));
+        assert_eq!(send_next(&mut queue, start).unwrap().text, "1");
+        assert_eq!(send_next(&mut queue, start).unwrap().text, "3");
+        assert_eq!(send_next(&mut queue, start).unwrap_err(), ms(3000));
+        assert_eq!(send_next(&mut queue, start + ms(3000)).unwrap().text, "2");
+    }
+
+    #[test]
+    fn full_channel_queues_drop_new_messages() {
+        let mut queue = queue(OutboundConfig {
+            max_queue: 2,
+            ..OutboundConfig::unlimited()
+        });
+        for text in ["1", "2", "3"] {
+            queue.push(say("a", text));
+        }
+        queue.push(say("b", "4"));
+        let texts = queue.messages.iter().map(|m| m.text.as_str());
+        assert_eq!(texts.collect::<Vec<_>>(), ["1", "2", "4"]);
+    }
+
+    #[test]
+    fn batches_are_queued_whole_or_not_at_all() {
+        let mut queue = queue(OutboundConfig {
+            max_queue: 3,
+            ..OutboundConfig::unlimited()
+        });
+        let batch = |texts: &[&str]| {
+            let messages = texts.iter().map(|text| match say("a", text) {
+                Outgoing::Message(message) => message,
+                _ => unreachable!(),
+            });
+            Outgoing::Batch(messages.collect())
+        };
+        queue.push(batch(&["1", "2"]));
+        queue.push(batch(&["3", "4"]));
+        queue.push(batch(&["5"]));
+        let texts = queue.messages.iter().map(|m| m.text.as_str());
+        assert_eq!(texts.collect::<Vec<_>>(), ["1", "2", "5"]);
+    }
+
+    fn out(channel: &str, text: &str) -> OutMessage {
+        OutMessage {
+            channel: channel.to_owned(),
+            text: text.to_owned(),
+            reply_to: Some("id".to_owned()),
+            retries: 0,
+        }
+    }
+
+    /// Records `message` as sent at `at`, returning whether it was varied.
+    fn record(queue: &mut Queue, message: OutMessage, at: Instant) -> bool {
+        let varied = queue.varies(&message, at);
+        let channel = message.channel.to_owned();
+        queue.last_sent.insert(
+            channel,
+            Sent {
+                message,
+                varied,
+                at,
+            },
+        );
+        varied
+    }
+
+    #[test]
+    fn every_other_repetition_is_varied() {
+        let mut queue = queue(OutboundConfig::unlimited());
+        let start = Instant::now();
+        let varied = [
+            record(&mut queue, out("a", "hi"), start),
+            record(&mut queue, out("a", "hi"), start + ms(1)),
+            record(&mut queue, out("a", "hi"), start + ms(2)),
+            record(&mut queue, out("a", "hi"), start + ms(3)),
+        ];
+        assert_eq!(varied, [false, true, false, true]);
+
+        // Not in another channel, with another text or after the window
+        assert!(!record(&mut queue, out("b", "hi"), start + ms(4)));
+        assert!(!record(&mut queue, out("a", "hello"), start + ms(5)));
+        assert!(!record(
+            &mut queue,
+            out("a", "hello"),
+            start + ms(5) + DUPLICATE_WINDOW
+        ));
+    }
+
+    #[test]
+    fn rejected_messages_are_retried_first() {
+        let mut queue = queue(OutboundConfig {
+            max_retries: 2,
+            ..OutboundConfig::unlimited()
+        });
+        let start = Instant::now();
+        record(&mut queue, out("a", "hi"), start);
+        queue.push(say("b", "other"));
+        queue.push(say("a", "next"));
+        queue.handle_notice("a".to_owned(), "msg_duplicate", start + ms(100));
+        let texts = queue.messages.iter().map(|m| m.text.as_str());
+        assert_eq!(texts.collect::<Vec<_>>(), ["other", "hi", "next"]);
+        let retry = &queue.messages[1];
+        assert_eq!(retry.retries, 1);
+        assert_eq!(retry.reply_to.as_deref(), Some("id"));
+        assert!(!queue.paused.contains_key("a"));
+
+        // Unknown notices and late ones aren't about the message
+        queue.handle_notice("a".to_owned(), "msg_banned", start + ms(100));
+        queue.handle_notice(
+            "a".to_owned(),
+            "msg_duplicate",
+            start + NOTICE_WINDOW + ms(1),
+        );
+        assert_eq!(queue.messages.len(), 3);
+    }
+
+    #[test]
+    fn retries_give_up_after_max_retries() {
+        let mut queue = queue(OutboundConfig {
+            max_retries: 1,
+            ..OutboundConfig::unlimited()
+        });
+        let start = Instant::now();
+        record(&mut queue, out("a", "hi"), start);
+        queue.handle_notice("a".to_owned(), "msg_duplicate", start);
+        let retry = send_next(&mut queue, start).unwrap();
+        record(&mut queue, retry, start);
+        queue.handle_notice("a".to_owned(), "msg_duplicate", start);
+        assert!(queue.messages.is_empty());
+    }
+
+    #[test]
+    fn rate_limits_pause_the_channel() {
+        let mut queue = queue(OutboundConfig {
+            backoff_ms: 5000,
+            channel: BucketConfig {
+                capacity: 3,
+                refill_ms: 1000,
+            },
+            ..OutboundConfig::unlimited()
+        });
+        let start = Instant::now();
+        queue.push(say("a", "hi"));
+        let sent = send_next(&mut queue, start).unwrap();
+        record(&mut queue, sent, start);
+        queue.handle_notice("a".to_owned(), "msg_ratelimit", start);
+        assert_eq!(queue.channels["a"].tokens, 0);
+        assert_eq!(send_next(&mut queue, start).unwrap_err(), ms(5000));
+        assert_eq!(send_next(&mut queue, start + ms(5000)).unwrap().text, "hi");
+    }
+}
@@ -0,0 +1,250 @@
+use crate::generation::{generate, GenerationConfig};
+use crate::models::{ModelKind, Models};
+
+use log::info;
+use markov_strings::MarkovResult;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the pools are checked even when no reply was taken.
+const REFILL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a paused refill checks whether it may go on.
+const PAUSE_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    /// Replies kept ready per model, 0 disables the pool
+    pub size: usize,
+    /// How many of the most recently used corpus, personal and live models
+    /// are kept ready
+    pub hot_models: usize,
+    /// A ready reply is thrown away once its model learned this many
+    /// messages since it was generated
+    pub refresh_after: usize,
+    /// Seconds to leave a model alone after it failed to generate
+    pub retry_secs: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 3,
+            hot_models: 8,
+            refresh_after: 500,
+            retry_secs: 60,
+        }
+    }
+}
+
+struct Pooled {
+    result: MarkovResult,
+    /// Size of the model's corpus when `result` was generated
+    corpus_len: usize,
+}
+
+struct Inner {
+    config: PoolConfig,
+    generation: GenerationConfig,
+    /// Bumped on every invalidation, so replies that were being generated
+    /// meanwhile can be told apart and dropped
+    epoch: u64,
+    pools: HashMap<ModelKind, VecDeque<Pooled>>,
+    /// Corpus, personal and live models by most recent use
+    hot: VecDeque<ModelKind>,
+    failed: HashMap<ModelKind, Instant>,
+}
+
+/// Replies generated ahead of time by a background thread, so commands can
+/// usually be answered without generating anything.
+#[derive(Clone)]
+pub struct ReplyPool {
+    inner: Arc<Mutex<Inner>>,
+    wake: mpsc::Sender<()>,
+    /// Set while a writer waits for the models, see `pause`
+    paused: Arc<AtomicBool>,
+}
+
+impl ReplyPool {
+    /// Starts the thread keeping the pools of `models` filled.
+    pub fn spawn(
+        models: Arc<RwLock<Models>>,
+        config: PoolConfig,
+        generation: GenerationConfig,
+    ) -> ReplyPool {
+        let inner = Arc::new(Mutex::new(Inner {
+            config,
+            generation,
+            epoch: 0,
+            pools: HashMap::new(),
+            hot: VecDeque::new(),
+            failed: HashMap::new(),
+        }));
+        let (wake, woken) = mpsc::channel();
+        let paused = Arc::new(AtomicBool::new(false));
+        let refill_inner = inner.clone();
+        let refill_paused = paused.clone();
+        thread::spawn(move || refill(models, refill_inner, woken, refill_paused));
+        ReplyPool {
+            inner,
+            wake,
+            paused,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes a ready reply of `kind`, and remembers that `kind` is in use.
+    pub fn take(&self, kind: &ModelKind) -> Option<MarkovResult> {
+        let mut inner = self.lock();
+        if kind != &ModelKind::Main && kind != &ModelKind::Combined {
+            inner.hot.retain(|hot| hot != kind);
+            inner.hot.push_front(kind.clone());
+            let keep = inner.config.hot_models.min(inner.hot.len());
+            for kind in inner.hot.split_off(keep) {
+                inner.pools.remove(&kind);
+            }
+        }
+        let result = inner
+            .pools
+            .get_mut(kind)
+            .and_then(|pool| pool.pop_front())
+            .map(|pooled| pooled.result);
+        drop(inner);
+        self.wake.send(()).ok();
+        result
+    }
+
+    /// Throws away every ready reply and generates new ones with
+    /// `generation`.
+    pub fn set_generation(&self, generation: GenerationConfig) {
+        let mut inner = self.lock();
+        inner.generation = generation;
+        inner.invalidate(None);
+        drop(inner);
+        self.wake.send(()).ok();
+    }
+
+    /// Keeps the pools from starting new generations while `paused`. Readers
+    /// of the models are never held back for a waiting `try_write`, so the
+    /// pools pause to let changes to the models through.
+    pub fn pause(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Release);
+    }
+
+    /// Throws away the ready replies of `kind`, or of every model if `None`,
+    /// e.g. because the model was replaced.
+    pub fn invalidate(&self, kind: Option<&ModelKind>) {
+        self.lock().invalidate(kind);
+        self.wake.send(()).ok();
+    }
+}
+
+impl Inner {
+    fn invalidate(&mut self, kind: Option<&ModelKind>) {
+        self.epoch += 1;
+        match kind {
+            Some(kind) => {
+                self.pools.remove(kind);
+                self.failed.remove(kind);
+            }
+            None => {
+                self.pools.clear();
+                self.failed.clear();
+            }
+        }
+    }
+
+    /// Drops stale replies and picks the next model whose pool needs a
+    /// reply.
+    fn next_job(&mut self, models: &Models) -> Option<ModelKind> {
+        let kinds = [ModelKind::Main, ModelKind::Combined]
+            .into_iter()
+            .chain(self.hot.iter().cloned())
+            .collect::<Vec<_>>();
+        let retry = Duration::from_secs(self.config.retry_secs);
+        for kind in kinds {
+            if models.get(&kind).is_none() {
+                continue;
+            }
+            if self
+                .failed
+                .get(&kind)
+                .is_some_and(|at| at.elapsed() < retry)
+            {
+                continue;
+            }
+            let corpus_len = models.train_data(&kind).map_or(0, Vec::len);
+            let refresh_after = self.config.refresh_after;
+            let pool = self.pools.entry(kind.clone()).or_default();
+            pool.retain(|pooled| corpus_len < pooled.corpus_len + refresh_after);
+            if pool.len() < self.config.size {
+                return Some(kind);
+            }
+        }
+        None
+    }
+}
+
+fn refill(
+    models: Arc<RwLock<Models>>,
+    inner: Arc<Mutex<Inner>>,
+    woken: mpsc::Receiver<()>,
+    paused: Arc<AtomicBool>,
+) {
+    let lock = || inner.lock().unwrap_or_else(PoisonError::into_inner);
+    loop {
+        if let Err(RecvTimeoutError::Disconnected) = woken.recv_timeout(REFILL_INTERVAL) {
+            return;
+        }
+        loop {
+            // The read lock is only held for one generation at a time, and
+            // not taken again while a change to the models waits for it.
+            while paused.load(Ordering::Acquire) {
+                thread::sleep(PAUSE_POLL);
+            }
+            // Wakes are only for the outer loop, but the bot may be gone
+            if let Err(TryRecvError::Disconnected) = woken.try_recv() {
+                return;
+            }
+            let models = models.read().unwrap_or_else(PoisonError::into_inner);
+            let (kind, generation, epoch) = {
+                let mut inner = lock();
+                let Some(kind) = inner.next_job(&models) else {
+                    break;
+                };
+                (kind, inner.generation.clone(), inner.epoch)
+            };
+            let corpus_len = models.train_data(&kind).map_or(0, Vec::len);
+            let deadline = Instant::now() + Duration::from_millis(generation.timeout_ms);
+            let result = models
+                .get(&kind)
+                .and_then(|m| generate(m, &generation, &kind, deadline).ok());
+            drop(models);
+
+            let mut inner = lock();
+            if inner.epoch != epoch {
+                continue;
+            }
+            match result {
+                Some(result) => inner
+                    .pools
+                    .entry(kind)
+                    .or_default()
+                    .push_back(Pooled { result, corpus_len }),
+                None => {
+                    info!("pool: failed to generate for {:?}", kind);
+                    inner.failed.insert(kind, Instant::now());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::files::write_atomically;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Everything changed through chat commands that should survive a restart,
+/// as changes to the lists in the config so those stay authoritative. Owners
+/// can't be changed through chat and always come from the config. Kept as
+/// JSON so it can be edited by hand while the bot is stopped.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct State {
+    /// Added with `!addmod`
+    pub moderators: BTreeSet<String>,
+    /// Moderators from the config removed with `!remmod`
+    pub removed_moderators: BTreeSet<String>,
+    /// Joined with `!join`
+    pub channels: BTreeSet<String>,
+    /// Channels from the config left with `!leave`
+    pub left_channels: BTreeSet<String>,
+}
+
+impl State {
+    /// Reads the state from `path`, starting empty if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<State, String> {
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("failed to parse {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        write_atomically(path, true, |writer| writer.write_all(json.as_bytes()))
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
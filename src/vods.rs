@@ -0,0 +1,225 @@
+use crate::importers::{Format, Manifest, MANIFEST};
+
+use log::{info, warn};
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A file or folder under `vods_dir` that couldn't be read.
+pub struct SkippedVod {
+    pub path: PathBuf,
+    pub error: String,
+    /// Comments read before the error
+    pub readable: usize,
+}
+
+impl fmt::Display for SkippedVod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({} comments readable)",
+            self.path.display(),
+            self.error,
+            self.readable
+        )
+    }
+}
+
+/// What `read_vods` read and skipped.
+#[derive(Default)]
+pub struct VodReport {
+    pub files: usize,
+    pub messages: usize,
+    pub skipped: Vec<SkippedVod>,
+}
+
+/// Calls `f` with the author and text of every chat message in
+/// `vods_dir/<streamer>` that passes `message_filter`, reading every
+/// streamer folder if `streamers` is empty. Each file is read by the
+/// importer of its format, see `Manifest`.
+///
+/// The files are parsed in parallel, a few at a time, but the messages are
+/// passed to `f` by streamer and file name so training gives the same models
+/// every time. Only the files being parsed are held in memory. Files that
+/// can't be read completely are skipped as a whole, or make it fail if
+/// `strict` is set.
+pub fn read_vods(
+    vods_dir: &Path,
+    streamers: &[String],
+    message_filter: Option<fn(String, String) -> bool>,
+    strict: bool,
+    mut f: impl FnMut(String, String),
+) -> Result<VodReport, String> {
+    let mut report = VodReport::default();
+    let skip = |report: &mut VodReport, skipped: SkippedVod| {
+        if strict {
+            return Err(format!("failed to read vods: {}", skipped));
+        }
+        report.skipped.push(skipped);
+        Ok(())
+    };
+    let streamer_entries = fs::read_dir(vods_dir)
+        .map_err(|e| format!("failed to read {}: {}", vods_dir.display(), e))?;
+    let mut streamer_dirs = Vec::new();
+    for streamer_entry in streamer_entries {
+        let streamer = match streamer_entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                let path = vods_dir.to_owned();
+                skip(&mut report, skipped(path, e, 0))?;
+                continue;
+            }
+        };
+        if !streamers.is_empty()
+            && !streamer
+                .file_name()
+                .is_some_and(|name| streamers.iter().any(|s| name.eq(s.as_str())))
+        {
+            continue;
+        }
+        if streamer.is_dir() {
+            streamer_dirs.push(streamer);
+        }
+    }
+    streamer_dirs.sort();
+
+    let mut progress = Vec::new();
+    let mut vods = Vec::new();
+    for streamer in streamer_dirs.iter() {
+        let manifest = match Manifest::load(streamer) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                skip(&mut report, skipped(streamer.join(MANIFEST), e, 0))?;
+                continue;
+            }
+        };
+        let mut paths = Vec::new();
+        match fs::read_dir(streamer) {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) if entry.file_name() == MANIFEST => {}
+                        Ok(entry) => paths.push(entry.path()),
+                        Err(e) => skip(&mut report, skipped(streamer.to_owned(), e, 0))?,
+                    }
+                }
+            }
+            Err(e) => skip(&mut report, skipped(streamer.to_owned(), e, 0))?,
+        }
+        paths.sort();
+        let index = progress.len();
+        progress.push(Progress {
+            streamer: streamer.file_name().unwrap_or_default().to_string_lossy(),
+            files: paths.len(),
+            done: AtomicUsize::new(0),
+        });
+        vods.extend(paths.into_iter().map(|path| {
+            let format = manifest.format(&path);
+            (index, path, format)
+        }));
+    }
+
+    // Enough files to keep every thread busy while the slowest of them is
+    // still being parsed
+    let window = rayon::current_num_threads() * 2;
+    for vods in vods.chunks(window) {
+        let results = vods
+            .par_iter()
+            .map(|(index, path, format)| {
+                let result = read_vod(path, *format, message_filter);
+                progress[*index].log(path, &result);
+                result
+            })
+            .collect::<Vec<_>>();
+        for ((_, path, _), result) in vods.iter().zip(results) {
+            match result {
+                Ok(vod) => {
+                    report.files += 1;
+                    report.messages += vod.messages.len();
+                    for (name, body) in vod.messages {
+                        f(name, body);
+                    }
+                }
+                Err((error, readable)) => {
+                    skip(&mut report, skipped(path.to_owned(), error, readable))?
+                }
+            }
+        }
+    }
+    info!(
+        "read {} vods, {} messages, skipped {}",
+        report.files,
+        report.messages,
+        report.skipped.len()
+    );
+    for skipped in report.skipped.iter() {
+        warn!("skipped {}", skipped);
+    }
+    Ok(report)
+}
+
+/// The messages of one vod that passed the filter.
+struct Vod {
+    /// Its format or format version
+    format: String,
+    comments: usize,
+    messages: Vec<(String, String)>,
+}
+
+/// How many of a streamer's vods were read so far.
+struct Progress<'a> {
+    streamer: Cow<'a, str>,
+    files: usize,
+    done: AtomicUsize,
+}
+
+impl Progress<'_> {
+    fn log(&self, path: &Path, result: &Result<Vod, (String, usize)>) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let file = path.file_name().unwrap_or_default().to_string_lossy();
+        let prefix = format!("{} {}/{}: {}", self.streamer, done, self.files, file);
+        match result {
+            Ok(vod) => info!("{} ({}), {} messages", prefix, vod.format, vod.comments),
+            Err((error, _)) => info!("{} failed, {}", prefix, error),
+        }
+    }
+}
+
+/// Reads the vod at `path` in `format`, returning the error and how many
+/// comments were read before it if that fails.
+fn read_vod(
+    path: &Path,
+    format: Option<Format>,
+    message_filter: Option<fn(String, String) -> bool>,
+) -> Result<Vod, (String, usize)> {
+    let format = format.ok_or_else(|| ("unknown chat log format".to_owned(), 0))?;
+    let mut comments = Vec::new();
+    let format = format
+        .importer()
+        .import(path, &mut |name, text| comments.push((name, text)))
+        .map_err(|e| (e, comments.len()))?;
+    let count = comments.len();
+    let messages = comments
+        .into_iter()
+        .map(|(name, body)| (name.to_lowercase(), body))
+        .filter(|(name, body)| {
+            message_filter.is_none_or(|filter| filter(name.to_owned(), body.to_owned()))
+        })
+        .collect();
+    Ok(Vod {
+        format,
+        comments: count,
+        messages,
+    })
+}
+
+fn skipped(path: PathBuf, error: impl ToString, readable: usize) -> SkippedVod {
+    SkippedVod {
+        path,
+        error: error.to_string(),
+        readable,
+    }
+}
@@ -0,0 +1,367 @@
+mod common;
+
+use common::{tests_dir, ScratchDir};
+use sglypa::chat::{MockChat, MockMessage};
+use sglypa::config::{ChannelProfile, Config};
+use sglypa::cooldown::Cooldown;
+use sglypa::outbound::OutboundConfig;
+use sglypa::state::State;
+use sglypa::{learn_filter, Sglypa};
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for a message the bot should send.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before deciding the bot ignored a message.
+const SILENCE: Duration = Duration::from_millis(300);
+
+const CHANNEL: &str = "chan";
+
+fn config(dir: &Path) -> Config {
+    let mut config = Config {
+        owners: vec!["owner".to_owned()],
+        vods_dir: tests_dir().join("vods"),
+        state_file: dir.join("state.json"),
+        snapshot: dir.join("sglypa.snapshot"),
+        autosave_secs: 0,
+        ..Config::default()
+    };
+    config.twitch.login = "bot".to_owned();
+    config.cooldowns.default = Cooldown::default();
+    config.pool.size = 0;
+    config
+}
+
+/// Starts the bot trained on `tests/vods`, returning the chat it is in.
+fn start(config: &Config) -> MockChat {
+    let (chat, sink, source) = MockChat::new();
+    let mut sglypa = Sglypa::new(
+        config,
+        Arc::new(sink),
+        Box::new(source),
+        OutboundConfig::unlimited(),
+    )
+    .unwrap();
+    sglypa
+        .train_from_vods(&config.vods_dir, &[], true, Some(learn_filter))
+        .unwrap();
+    sglypa.join_remembered();
+    tokio::spawn(async move { sglypa.run().await });
+    chat
+}
+
+async fn expect(chat: &mut MockChat) -> MockMessage {
+    chat.next(TIMEOUT).await.expect("the bot said nothing")
+}
+
+async fn expect_silence(chat: &mut MockChat) {
+    if let Some(message) = chat.next(SILENCE).await {
+        panic!("the bot said {:?}", message);
+    }
+}
+
+#[tokio::test]
+async fn on_lets_everyone_use_commands() {
+    let dir = ScratchDir::new("on");
+    let mut chat = start(&config(&dir));
+
+    chat.send("viewer", CHANNEL, "!listowners");
+    expect_silence(&mut chat).await;
+
+    chat.send("viewer", CHANNEL, "!on");
+    expect_silence(&mut chat).await;
+    chat.send("owner", CHANNEL, "!on");
+    chat.send("viewer", CHANNEL, "!listowners");
+    let message = expect(&mut chat).await;
+    assert_eq!(message.channel, CHANNEL);
+    assert!(message.text.starts_with("Owners: "), "{:?}", message);
+    assert!(message.text.contains("owner"), "{:?}", message);
+
+    // Only in the channel it was turned on in
+    chat.send("viewer", "elsewhere", "!listowners");
+    expect_silence(&mut chat).await;
+
+    chat.send("owner", CHANNEL, "!off");
+    chat.send("viewer", CHANNEL, "!listowners");
+    expect_silence(&mut chat).await;
+}
+
+#[tokio::test]
+async fn addmod_grants_moderator_commands() {
+    let dir = ScratchDir::new("addmod");
+    let config = config(&dir);
+    let mut chat = start(&config);
+
+    chat.send("viewer", CHANNEL, "!st 2 hi");
+    expect_silence(&mut chat).await;
+    chat.send("viewer", CHANNEL, "!addmod viewer");
+    expect_silence(&mut chat).await;
+
+    let id = chat.send("owner", CHANNEL, "!addmod @Viewer");
+    let message = expect(&mut chat).await;
+    assert_eq!(message.text, "Sucessfully added viewer to moderators");
+    assert_eq!(message.reply_to, Some(id));
+    let state = fs::read_to_string(&config.state_file).unwrap();
+    assert!(state.contains("\"viewer\""), "{}", state);
+
+    chat.send("viewer", CHANNEL, "!st 2 hi");
+    let stair = [
+        expect(&mut chat).await.text,
+        expect(&mut chat).await.text,
+        expect(&mut chat).await.text,
+    ];
+    assert_eq!(stair, ["hi", "hi hi", "hi"]);
+
+    chat.send("owner", CHANNEL, "!addmod viewer");
+    assert_eq!(expect(&mut chat).await.text, "viewer is already moderator");
+    chat.send("owner", CHANNEL, "!remmod viewer");
+    assert_eq!(
+        expect(&mut chat).await.text,
+        "Sucessfully removed viewer from moderators"
+    );
+    chat.send("viewer", CHANNEL, "!st 2 hi");
+    expect_silence(&mut chat).await;
+}
+
+#[tokio::test]
+async fn commands_only_run_by_their_exact_name() {
+    let dir = ScratchDir::new("exact");
+    let mut config = config(&dir);
+    config.generation.relax.learned = vec![0.0];
+    let mut chat = start(&config);
+
+    chat.send("owner", CHANNEL, "!stairs 2 hi");
+    chat.send("owner", CHANNEL, "!status 2 hi");
+    expect_silence(&mut chat).await;
+
+    chat.send("alice", CHANNEL, "опять шахматы как всегда");
+    chat.send("owner", CHANNEL, "!resetfoo");
+    chat.send("owner", CHANNEL, "!nglypa");
+    let message = expect(&mut chat).await;
+    assert!(message.text.starts_with("Sglypa: "), "{:?}", message);
+
+    chat.send("owner", CHANNEL, "!reset");
+    chat.send("owner", CHANNEL, "!nglypa");
+    expect_silence(&mut chat).await;
+}
+
+#[tokio::test]
+async fn stairs_to_another_channel() {
+    let dir = ScratchDir::new("stairs");
+    let mut chat = start(&config(&dir));
+
+    chat.send("owner", CHANNEL, "!<> other 3 a b");
+    let mut stair = Vec::new();
+    for _ in 0..5 {
+        let message = expect(&mut chat).await;
+        assert_eq!(message.channel, "other");
+        assert_eq!(message.reply_to, None);
+        stair.push(message.text);
+    }
+    assert_eq!(stair, ["a b", "a b a b", "a b a b a b", "a b a b", "a b"]);
+    expect_silence(&mut chat).await;
+
+    chat.send("owner", CHANNEL, "!<>other 2 c");
+    for text in ["c", "c c", "c"] {
+        let message = expect(&mut chat).await;
+        assert_eq!(message.channel, "other");
+        assert_eq!(message.text, text);
+    }
+    expect_silence(&mut chat).await;
+
+    chat.send("owner", CHANNEL, "!st");
+    let message = expect(&mut chat).await;
+    assert!(message.text.starts_with("!st "), "{:?}", message);
+}
+
+#[tokio::test]
+async fn settings_are_shown_changed_and_reset() {
+    let dir = ScratchDir::new("settings");
+    let mut chat = start(&config(&dir));
+
+    chat.send("viewer", CHANNEL, "!settings MIN_LEN 30");
+    expect_silence(&mut chat).await;
+
+    chat.send("owner", CHANNEL, "!settings");
+    let message = expect(&mut chat).await;
+    assert!(message.text.contains("MIN_LEN=120 "), "{:?}", message);
+
+    chat.send("owner", CHANNEL, "!settings MIN_LEN 30 MAX_LEN");
+    let message = expect(&mut chat).await;
+    assert!(message.text.contains("MIN_LEN=30 "), "{:?}", message);
+    assert!(message.text.contains("(failed: "), "{:?}", message);
+    assert!(message.text.contains("MAX_LEN"), "{:?}", message);
+
+    chat.send("owner", CHANNEL, "!settings show");
+    let message = expect(&mut chat).await;
+    assert!(message.text.contains("MIN_LEN=30 "), "{:?}", message);
+    assert!(!message.text.contains("failed"), "{:?}", message);
+
+    chat.send("owner", CHANNEL, "!settings reset");
+    let message = expect(&mut chat).await;
+    assert!(message.text.contains("MIN_LEN=120 "), "{:?}", message);
+}
+
+#[tokio::test]
+async fn settings_must_suit_the_channel_profiles() {
+    let dir = ScratchDir::new("profiles");
+    let mut config = config(&dir);
+    let profile = ChannelProfile {
+        max_len: Some(120),
+        ..ChannelProfile::default()
+    };
+    config.profiles.insert("other".to_owned(), profile);
+    let mut chat = start(&config);
+
+    chat.send("owner", CHANNEL, "!settings MIN_LEN 150 MIN_REFS 2");
+    let message = expect(&mut chat).await;
+    assert!(message.text.contains("MIN_LEN=120 "), "{:?}", message);
+    assert!(message.text.contains("MIN_REFS=2 "), "{:?}", message);
+    assert!(
+        message
+            .text
+            .contains("MIN_LEN conflicts with profile other: "),
+        "{:?}",
+        message
+    );
+}
+
+#[tokio::test]
+async fn filter_takes_the_rest_of_the_line() {
+    let dir = ScratchDir::new("filter");
+    let mut chat = start(&config(&dir));
+
+    chat.send("owner", CHANNEL, "!settings MIN_LEN 30 FILTER foo bar");
+    let message = expect(&mut chat).await;
+    assert!(message.text.contains("MIN_LEN=30 "), "{:?}", message);
+    assert!(message.text.contains("FILTER=\"foo bar\""), "{:?}", message);
+    assert!(!message.text.contains("failed"), "{:?}", message);
+
+    chat.send("owner", CHANNEL, "!settings FILTER");
+    let message = expect(&mut chat).await;
+    assert!(message.text.contains("FILTER=\"\""), "{:?}", message);
+    assert!(!message.text.contains("failed"), "{:?}", message);
+}
+
+#[tokio::test]
+async fn info_counts_messages_by_chatter() {
+    let dir = ScratchDir::new("info");
+    let mut chat = start(&config(&dir));
+
+    chat.send("owner", CHANNEL, "!on");
+    chat.send("Viewer", CHANNEL, "!info");
+    let message = expect(&mut chat).await;
+    assert_eq!(
+        message.text,
+        "@Viewer Top 20 chatters:\talice: 3;\tbob: 2;\tcarol: 1;\t"
+    );
+
+    chat.send("Viewer", CHANNEL, "!info @Alice");
+    let message = expect(&mut chat).await;
+    assert_eq!(
+        message.text,
+        "@Viewer Found 3 relevant messages from @Alice"
+    );
+}
+
+#[tokio::test]
+async fn learning_lands_while_the_pool_refills() {
+    let dir = ScratchDir::new("refill");
+    let mut config = config(&dir);
+    // The pool never fills up, so it keeps generating
+    config.pool.size = usize::MAX;
+    config.generation.relax.main = vec![0.0];
+    let mut chat = start(&config);
+    tokio::time::sleep(SILENCE).await;
+
+    chat.send("owner", CHANNEL, "!on");
+    chat.send("alice", CHANNEL, "опять шахматы как всегда");
+    let learned = "@Viewer Found 4 relevant messages from alice";
+    for _ in 0..20 {
+        chat.send("Viewer", CHANNEL, "!info alice");
+        if expect(&mut chat).await.text == learned {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("the message was never learned");
+}
+
+#[tokio::test]
+async fn save_writes_the_snapshot_in_the_background() {
+    let dir = ScratchDir::new("save");
+    let config = config(&dir);
+    let mut chat = start(&config);
+    assert!(!config.snapshot.exists());
+
+    let id = chat.send("owner", CHANNEL, "!save");
+    // Chat is handled while the snapshot is written
+    chat.send("owner", CHANNEL, "!listowners");
+    let mut replies = [expect(&mut chat).await, expect(&mut chat).await];
+    replies.sort_by_key(|message| message.text.clone());
+    assert!(replies[0].text.starts_with("Owners: "), "{:?}", replies);
+    assert_eq!(replies[1].text, "Saved snapshot");
+    assert_eq!(replies[1].reply_to, Some(id));
+    assert!(config.snapshot.exists());
+}
+
+#[tokio::test]
+async fn join_and_leave_go_through_the_sink() {
+    let dir = ScratchDir::new("join");
+    let config = Config {
+        channels: vec!["Home".to_owned()],
+        ..config(&dir)
+    };
+    let mut chat = start(&config);
+    assert_eq!(chat.joined(), ["home"]);
+
+    chat.send("owner", CHANNEL, "!join Other");
+    chat.send("owner", CHANNEL, "!leave home");
+    chat.send("owner", CHANNEL, "!listowners");
+    expect(&mut chat).await;
+    assert_eq!(chat.joined(), ["other"]);
+    let state = State::load(&config.state_file).unwrap();
+    assert_eq!(state.channels.iter().collect::<Vec<_>>(), ["other"]);
+    assert_eq!(state.left_channels.iter().collect::<Vec<_>>(), ["home"]);
+
+    // Left config channels stay left after a restart
+    let chat = start(&config);
+    assert_eq!(chat.joined(), ["other"]);
+}
+
+#[tokio::test]
+async fn owners_only_come_from_the_config() {
+    let dir = ScratchDir::new("owners");
+    let config = Config {
+        moderators: vec!["Helper".to_owned()],
+        ..config(&dir)
+    };
+    let mut chat = start(&config);
+    chat.send("owner", CHANNEL, "!addmod viewer");
+    expect(&mut chat).await;
+    chat.send("owner", CHANNEL, "!remmod helper");
+    expect(&mut chat).await;
+    let state = State::load(&config.state_file).unwrap();
+    assert_eq!(state.moderators.iter().collect::<Vec<_>>(), ["viewer"]);
+    assert_eq!(
+        state.removed_moderators.iter().collect::<Vec<_>>(),
+        ["helper"]
+    );
+
+    // Removing an owner from the config revokes them
+    let config = Config {
+        owners: Vec::new(),
+        ..config
+    };
+    let mut chat = start(&config);
+    chat.send("owner", CHANNEL, "!listowners");
+    expect_silence(&mut chat).await;
+    chat.send("viewer", CHANNEL, "!listowners");
+    assert_eq!(expect(&mut chat).await.text, "Owners: bot, ");
+    chat.send("helper", CHANNEL, "!listowners");
+    expect_silence(&mut chat).await;
+}
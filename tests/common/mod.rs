@@ -0,0 +1,23 @@
+// Every test crate uses a different part of this
+#![allow(dead_code, unused_imports)]
+
+pub use sglypa::files::ScratchDir;
+use sglypa::vods::read_vods;
+
+use std::path::{Path, PathBuf};
+
+/// The `tests` directory with the fixtures.
+pub fn tests_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+/// The author and text of every message in `vods_dir/<folder>`, which must
+/// all be readable.
+pub fn read(vods_dir: &Path, folder: &str) -> Vec<(String, String)> {
+    let mut messages = Vec::new();
+    read_vods(vods_dir, &[folder.to_owned()], None, true, |name, text| {
+        messages.push((name, text))
+    })
+    .unwrap();
+    messages
+}
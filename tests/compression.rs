@@ -0,0 +1,64 @@
+mod common;
+
+use common::{read, tests_dir, ScratchDir};
+use sglypa::compression::{compress_vods, Compression};
+
+use std::fs;
+
+/// The messages of the uncompressed `tests/vods/streamer/chat.json`.
+fn plain() -> Vec<(String, String)> {
+    let messages = read(&tests_dir().join("vods"), "streamer");
+    assert_eq!(messages.len(), 7);
+    messages
+}
+
+#[test]
+fn reads_gzip_logs() {
+    assert_eq!(read(&tests_dir().join("logs"), "gzip"), plain());
+}
+
+#[test]
+fn reads_zstd_logs() {
+    assert_eq!(read(&tests_dir().join("logs"), "zstd"), plain());
+}
+
+/// Compresses a copy of `tests/vods/streamer` with `compression`, checking
+/// that only the compressed log is left and reads the same.
+fn round_trip(compression: Compression, level: Option<i32>) {
+    let dir = ScratchDir::new(&format!("compress-{:?}", compression));
+    fs::create_dir_all(dir.join("streamer")).unwrap();
+    fs::copy(
+        tests_dir().join("vods/streamer/chat.json"),
+        dir.join("streamer/chat.json"),
+    )
+    .unwrap();
+    fs::write(dir.join("streamer/notes.md"), "not a chat log").unwrap();
+
+    compress_vods(&dir, compression, level).unwrap();
+    let mut files = fs::read_dir(dir.join("streamer"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    let compressed = format!("chat.json.{}", compression.extension());
+    assert_eq!(files, [compressed.as_str(), "notes.md"]);
+
+    fs::remove_file(dir.join("streamer/notes.md")).unwrap();
+    assert_eq!(read(&dir, "streamer"), plain());
+
+    // Compressed logs are left alone
+    compress_vods(&dir, compression, level).unwrap();
+    assert!(dir.join("streamer").join(&compressed).exists());
+}
+
+#[test]
+fn compresses_with_gzip() {
+    round_trip(Compression::Gzip, None);
+    round_trip(Compression::Gzip, Some(9));
+}
+
+#[test]
+fn compresses_with_zstd() {
+    round_trip(Compression::Zstd, None);
+    round_trip(Compression::Zstd, Some(19));
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::tests_dir;
+use sglypa::downloader::{read_chat, Comment, Id, Version};
+
+use std::path::PathBuf;
+
+fn chat(name: &str) -> PathBuf {
+    tests_dir().join("chats").join(name)
+}
+
+/// The login and text of every comment in `tests/chats/<name>`, and the
+/// error if reading it failed.
+fn read(name: &str) -> (Vec<(String, String)>, Result<(), String>) {
+    let mut comments = Vec::new();
+    let result = read_chat(&chat(name), |comment: Comment| {
+        comments.push((comment.commenter.name, comment.message.text()))
+    });
+    (comments, result.map(|_| ()))
+}
+
+#[test]
+fn reads_the_header_and_every_comment() {
+    let mut comments = Vec::new();
+    let header = read_chat(&chat("v1.json"), |comment| comments.push(comment)).unwrap();
+    assert_eq!(
+        header.version(),
+        Some(Version {
+            major: 1,
+            minor: 3,
+            patch: 1
+        })
+    );
+    let streamer = header.streamer.unwrap();
+    assert_eq!(streamer.name, "Streamer");
+    assert_eq!(streamer.id, Some(Id::Number(123)));
+    let video = header.video.unwrap();
+    assert_eq!(video.id, Some(Id::Text("456".to_owned())));
+    assert_eq!(video.view_count, Some(10));
+
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[0].id.as_deref(), Some("c1"));
+    assert_eq!(comments[0].commenter.id, Some(Id::Text("1".to_owned())));
+    let badge = &comments[0].message.user_badges[0];
+    assert_eq!(badge.id.as_deref(), Some("subscriber"));
+    assert_eq!(comments[1].commenter.id, Some(Id::Number(2)));
+    let emoticon = comments[1].message.fragments[1].emoticon.as_ref().unwrap();
+    assert_eq!(emoticon.emoticon_id, Some(Id::Text("25".to_owned())));
+}
+
+#[test]
+fn text_falls_back_to_the_fragments() {
+    let (comments, result) = read("v1.json");
+    result.unwrap();
+    assert_eq!(
+        comments,
+        [
+            ("alice".to_owned(), "hello chat".to_owned()),
+            ("bob".to_owned(), "hi Kappa".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn rejects_newer_major_versions() {
+    let (comments, result) = read("v2.json");
+    let error = result.unwrap_err();
+    assert!(
+        error.contains("unsupported TwitchDownloader format version 2.3.1"),
+        "{}",
+        error
+    );
+    assert!(comments.is_empty());
+}
+
+#[test]
+fn reads_legacy_logs_without_file_info() {
+    let mut comments = Vec::new();
+    let header = read_chat(&chat("legacy.json"), |comment| {
+        comments.push((comment.commenter.name, comment.message.text()))
+    })
+    .unwrap();
+    assert!(header.file_info.is_none());
+    assert_eq!(header.version(), None);
+    assert_eq!(
+        comments,
+        [
+            ("carol".to_owned(), "old message".to_owned()),
+            ("dave".to_owned(), "plain body".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn tolerates_unused_fields_of_other_shapes() {
+    let (comments, result) = read("loose.json");
+    result.unwrap();
+    assert_eq!(
+        comments,
+        [
+            ("alice".to_owned(), "hello chat".to_owned()),
+            ("bob".to_owned(), "hi Kappa".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn fails_without_comments() {
+    let (_, result) = read("no_comments.json");
+    let error = result.unwrap_err();
+    assert!(error.contains("missing field `comments`"), "{}", error);
+}
+
+#[test]
+fn passes_comments_on_before_a_truncation() {
+    let (comments, result) = read("truncated.json");
+    assert!(result.is_err());
+    assert_eq!(comments, [("alice".to_owned(), "hello chat".to_owned())]);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::tests_dir;
+use sglypa::vods::read_vods;
+
+use std::path::PathBuf;
+
+fn logs_dir() -> PathBuf {
+    tests_dir().join("logs")
+}
+
+/// The author and text of every message in `tests/logs/<folder>`.
+fn read(folder: &str) -> Vec<(String, String)> {
+    common::read(&logs_dir(), folder)
+}
+
+fn pairs(messages: &[(&str, &str)]) -> Vec<(String, String)> {
+    messages
+        .iter()
+        .map(|(name, text)| (name.to_string(), text.to_string()))
+        .collect()
+}
+
+#[test]
+fn chatterino_skips_system_lines() {
+    assert_eq!(
+        read("chatterino"),
+        pairs(&[
+            ("alice", "hello chat"),
+            ("bob", "hi alice"),
+            ("apple_fan", "nice stream"),
+            ("alice", "what's up: nothing much"),
+        ])
+    );
+}
+
+#[test]
+fn irc_reads_privmsgs_only() {
+    assert_eq!(
+        read("irc"),
+        pairs(&[("alice", "hello chat"), ("bob", "waves")])
+    );
+}
+
+#[test]
+fn yt_dlp_reads_text_and_paid_messages() {
+    assert_eq!(
+        read("youtube"),
+        pairs(&[("alice", "hello 😀"), ("bob", "great stream :yt:")])
+    );
+}
+
+#[test]
+fn csv_finds_the_columns_by_header() {
+    assert_eq!(
+        read("csv"),
+        pairs(&[
+            ("alice", "hello chat"),
+            ("bob", "hi, alice"),
+            ("carol", "she said \"hi\""),
+        ])
+    );
+}
+
+#[test]
+fn manifest_overrides_the_extension() {
+    assert_eq!(
+        read("manifest"),
+        pairs(&[("alice", "from irc"), ("bob", "from chatterino")])
+    );
+}
+
+#[test]
+fn manifest_patterns_ignore_the_compression_extension() {
+    assert_eq!(
+        read("manifest-compressed"),
+        pairs(&[("alice", "compressed irc")])
+    );
+}
+
+#[test]
+fn unknown_formats_are_skipped() {
+    let mut messages = Vec::new();
+    let folders = ["unknown".to_owned()];
+    let report = read_vods(&logs_dir(), &folders, None, false, |name, text| {
+        messages.push((name, text))
+    })
+    .unwrap();
+    assert_eq!(messages, pairs(&[("alice", "still read")]));
+    assert_eq!(report.skipped.len(), 1);
+    assert!(report.skipped[0].path.ends_with("readme.md"));
+    assert_eq!(report.skipped[0].error, "unknown chat log format");
+
+    let error = read_vods(&logs_dir(), &folders, None, true, |_, _| {})
+        .err()
+        .unwrap();
+    assert!(error.contains("unknown chat log format"), "{}", error);
+}